@@ -0,0 +1,90 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> time_buffer: TimeBuffer;
+
+struct TimeBuffer {
+    elapsed_time: f32,
+};
+
+// Outputs of the buffer passes, see the README
+@group(3) @binding(0)
+var buffer_sampler: sampler;
+
+@group(3) @binding(1)
+var buffer_a: texture_2d<f32>;
+
+@group(3) @binding(2)
+var buffer_b: texture_2d<f32>;
+
+// See waves.wgsl for an explanation
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var result: VertexOutput;
+    let x = i32(vertex_index) / 2;
+    let y = i32(vertex_index) & 1;
+    let tc = vec2<f32>(
+        f32(x) * 2.0,
+        f32(y) * 2.0
+    );
+    result.position = vec4<f32>(
+        tc.x * 2.0 - 1.0,
+        1.0 - tc.y * 2.0,
+        0.0, 1.0
+    );
+    result.tex_coords = tc;
+    return result;
+}
+
+const glowColor : vec3<f32> = vec3<f32>(0.5, 0.1, 0.7);
+const blurRadius : i32 = 8;
+const blurSpread : f32 = 0.004;
+
+// Textures can't be passed to functions on every backend, so both halves of the blur spell out the loop
+fn blurWeight(i: i32) -> f32 {
+    return exp(-f32(i * i) / f32(blurRadius * blurRadius) * 2.0);
+}
+
+// Buffer A: a few bright moving dots
+@fragment
+fn fs_buffer_a(input: VertexOutput) -> @location(0) vec4<f32> {
+    let t = time_buffer.elapsed_time;
+    var light = 0.0;
+    for (var i: i32 = 0; i < 5; i++) {
+        let phase = f32(i) * 1.3;
+        let center = vec2<f32>(0.5 + 0.35 * cos(t * 0.5 + phase), 0.5 + 0.35 * sin(t * 0.7 + phase * 2.0));
+        light += smoothstep(0.02, 0.0, distance(input.tex_coords, center));
+    }
+    return vec4<f32>(glowColor * light * 2.0, 1.0);
+}
+
+// Buffer B: horizontal half of the blur
+@fragment
+fn fs_buffer_b(input: VertexOutput) -> @location(0) vec4<f32> {
+    var sum = vec4<f32>(0.0);
+    var weights = 0.0;
+    for (var i: i32 = -blurRadius; i <= blurRadius; i++) {
+        let offset = vec2<f32>(f32(i) * blurSpread, 0.0);
+        sum += textureSample(buffer_a, buffer_sampler, input.tex_coords + offset) * blurWeight(i);
+        weights += blurWeight(i);
+    }
+    return sum / weights;
+}
+
+// Vertical half of the blur, on top of the sharp dots
+@fragment
+fn fs_main(input: VertexOutput) -> @location(0) vec4<f32> {
+    let sharp = textureSample(buffer_a, buffer_sampler, input.tex_coords);
+    var glow = vec4<f32>(0.0);
+    var weights = 0.0;
+    for (var i: i32 = -blurRadius; i <= blurRadius; i++) {
+        let offset = vec2<f32>(0.0, f32(i) * blurSpread);
+        glow += textureSample(buffer_b, buffer_sampler, input.tex_coords + offset) * blurWeight(i);
+        weights += blurWeight(i);
+    }
+    glow /= weights;
+    return vec4<f32>(sharp.rgb + glow.rgb * 3.0, 1.0);
+}
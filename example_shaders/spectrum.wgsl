@@ -0,0 +1,72 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> time_buffer: TimeBuffer;
+
+struct TimeBuffer {
+    elapsed_time: f32,
+};
+
+// x: channels, y: samples per channel, z: fft bins per channel, w: sample rate
+@group(2) @binding(0)
+var<uniform> audio_info: vec4<u32>;
+
+@group(2) @binding(1)
+var<storage, read> waveform: array<f32>;
+
+@group(2) @binding(2)
+var<storage, read> spectrum: array<f32>;
+
+// See waves.wgsl for an explanation
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var result: VertexOutput;
+    let x = i32(vertex_index) / 2;
+    let y = i32(vertex_index) & 1;
+    let tc = vec2<f32>(
+        f32(x) * 2.0,
+        f32(y) * 2.0
+    );
+    result.position = vec4<f32>(
+        tc.x * 2.0 - 1.0,
+        1.0 - tc.y * 2.0,
+        0.0, 1.0
+    );
+    result.tex_coords = tc;
+    return result;
+}
+
+const barColor : vec3<f32> = vec3<f32>(0.5, 0.1, 0.7);
+const waveColor : vec3<f32> = vec3<f32>(0.9, 0.9, 0.9);
+// Only the lower part of the spectrum is interesting
+const shownBins : f32 = 0.25;
+
+@fragment
+fn fs_main(input: VertexOutput) -> @location(0) vec4<f32> {
+    let channels = audio_info.x;
+    let samples = audio_info.y;
+    let bins = audio_info.z;
+    if (channels == 0u) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+    let uv = input.tex_coords;
+
+    // Left half of the screen shows channel 0, right half the last channel
+    let channel = min(u32(uv.x * f32(channels)), channels - 1u);
+    let x = fract(uv.x * f32(channels));
+
+    let bin = u32(x * f32(bins) * shownBins);
+    let magnitude = log(1.0 + spectrum[channel * bins + bin]) / 4.0;
+    let bar = step(1.0 - uv.y, magnitude);
+
+    let sample = u32(x * f32(samples));
+    let amplitude = waveform[channel * samples + sample];
+    let wave = smoothstep(0.01, 0.0, abs(0.5 - amplitude * 0.5 - uv.y));
+
+    let hue = 0.5 + 0.5 * cos(time_buffer.elapsed_time * 0.5);
+    let color = bar * mix(barColor, barColor.bgr, hue) + wave * waveColor;
+    return vec4<f32>(color, 1.0);
+}
@@ -0,0 +1,65 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> time_buffer: TimeBuffer;
+
+struct TimeBuffer {
+    elapsed_time: f32,
+};
+
+@group(1) @binding(1)
+var<uniform> pointer_buffer: array<vec4<f32>,10>;
+
+@group(3) @binding(0)
+var buffer_sampler: sampler;
+
+// What fs_main rendered the frame before, see the README
+@group(3) @binding(5)
+var previous_frame: texture_2d<f32>;
+
+// See waves.wgsl for an explanation
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var result: VertexOutput;
+    let x = i32(vertex_index) / 2;
+    let y = i32(vertex_index) & 1;
+    let tc = vec2<f32>(
+        f32(x) * 2.0,
+        f32(y) * 2.0
+    );
+    result.position = vec4<f32>(
+        tc.x * 2.0 - 1.0,
+        1.0 - tc.y * 2.0,
+        0.0, 1.0
+    );
+    result.tex_coords = tc;
+    return result;
+}
+
+const trailColor : vec3<f32> = vec3<f32>(0.5, 0.1, 0.7);
+// Share of the last frame that is kept, closer to 1 gives longer trails
+const persistence : f32 = 0.96;
+// The last frame is sampled slightly zoomed in, so the trails drift outwards
+const zoom : f32 = 0.995;
+
+@fragment
+fn fs_main(input: VertexOutput) -> @location(0) vec4<f32> {
+    let t = time_buffer.elapsed_time;
+    let uv = input.tex_coords;
+
+    let previous = textureSample(previous_frame, buffer_sampler, (uv - 0.5) * zoom + 0.5).rgb;
+
+    var light = 0.0;
+    for (var i: i32 = 0; i < 3; i++) {
+        let phase = f32(i) * 2.1;
+        let center = vec2<f32>(0.5 + 0.3 * cos(t * 0.9 + phase), 0.5 + 0.3 * sin(t * 1.3 + phase));
+        light += smoothstep(0.015, 0.0, distance(uv, center));
+    }
+    let pointer = vec2(pointer_buffer[0].x, pointer_buffer[0].y);
+    light += smoothstep(0.01, 0.0, distance(uv, pointer));
+
+    return vec4<f32>(previous * persistence + trailColor * light, 1.0);
+}
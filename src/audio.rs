@@ -1,137 +1,607 @@
-use psimple::Simple;
-use pulse::def::BufferAttr;
-use pulse::sample::{Format, Spec};
-use pulse::stream::Direction;
+mod bands;
+mod cqt;
+mod error;
+mod export;
+mod features;
+mod levels;
+mod onset;
+mod pcm;
+#[cfg(feature = "pipewire")]
+mod pipewire;
+mod pitch;
+mod pulse;
+mod scope;
+mod smoothing;
+mod stereo;
+mod tempo;
+mod wav;
+mod window;
+
+pub use bands::{BandConfig, BandScale};
+pub use error::AudioError;
+pub use export::{BarsFormat, BarsOutput};
+pub use pcm::{PcmFormat, PcmSource};
+#[cfg(feature = "pipewire")]
+pub use pipewire::PipewireSource;
+pub use pulse::{default_monitor, list_sources, PulseSource};
+pub use scope::ScopeTrigger;
+pub use stereo::stereo_buffer_len;
+pub use wav::WavSource;
+pub use window::WindowFunction;
+
+use bands::BandAggregator;
+use cqt::ConstantQ;
+use export::BarsExport;
+use features::SpectralFeatures;
+use levels::LevelMeter;
+use onset::OnsetDetector;
+use pitch::PitchDetector;
 use rustfft::num_complex::Complex;
 use rustfft::FftPlanner;
+use scope::Scope;
+use smoothing::{AutoGain, Smoother};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tempo::TempoTracker;
+use triple_buffer::{Input, Output, TripleBuffer};
 
-pub struct AudioInput {
-    pub device_name: Option<String>,
+/// Where the samples come from
+pub trait AudioSource: Send {
+    fn channels(&self) -> usize;
+    fn sample_rate(&self) -> u32;
+    /// Fills `data` with interleaved samples, blocking until they are available.
+    /// The data is stored this way:
+    /// sample = [ channel-0 , channel-1, ... channel-n]
+    fn read(&mut self, data: &mut [f32]) -> Result<(), AudioError>;
+    /// Something to call from another thread to make a blocked `read` return an error soon,
+    /// so the capture can stop. Sources whose reads always return within a hop don't need one.
+    fn waker(&self) -> Option<Waker> {
+        None
+    }
+}
+
+/// Wakes up a `read` blocked on another thread, it keeps failing afterwards
+pub type Waker = Box<dyn Fn() + Send>;
+
+/// Opens the source, again after it failed, eg. when the sound server restarted,
+/// or every time the capture gets started
+pub type SourceOpener = Arc<dyn Fn() -> Result<Box<dyn AudioSource>, AudioError> + Send + Sync>;
+
+// How long to wait before trying to reopen a failed source, doubled on every failed attempt
+const MIN_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
+pub struct AudioConfig {
     pub channels: usize,
     pub sample_rate: u32,
+    // Samples per channel of the analysed window, and size of the fft
     pub buffer_size: u32,
-    pub used: bool,
+    // Samples per channel read from the device between two analysed windows
+    pub hop_size: u32,
+    pub window: WindowFunction,
+    // Leave the fft to the gpu, only the waveform gets produced
+    pub gpu_fft: bool,
+    // Give the shader a constant-Q spectrum with this many bins per octave, between the band frequencies
+    pub cqt_bins_per_octave: Option<u32>,
+    // Height of the spectrogram texture, one spectrum per row
+    pub spectrogram_rows: u32,
+    // Samples per channel of the oscilloscope window, and how it gets placed
+    pub scope_samples: u32,
+    pub scope_trigger: ScopeTrigger,
+    pub bands: BandConfig,
+    // How much the spectral flux has to exceed its recent average to count as an onset
+    pub beat_sensitivity: f32,
+    // Attack and release time constants of the spectrum and band smoothing, in seconds
+    pub attack: f32,
+    pub release: f32,
+    // Length of the automatic gain control window in seconds, None disables it
+    pub agc_window: Option<f32>,
+    // Where the band magnitudes get written for other programs, if anywhere
+    pub bars_output: Option<BarsOutput>,
+}
+
+/// Everything the analysis of one window produces, handed over to the renderer as a whole
+#[derive(Clone)]
+pub struct AudioFrame {
     pub audio_buffers: Vec<Vec<f32>>,
     pub fft_buffers: Vec<Vec<f32>>,
+    // Band magnitudes of all the channels mixed together: [magnitude, onset (0 or 1), flux, padding]
+    pub bands: Vec<[f32; 4]>,
+    // Levels of the raw samples, all the channels together then every channel:
+    // [rms, peak, held peak, short-term loudness in LUFS]
+    pub levels: Vec<[f32; 4]>,
+    // Energy of the 12 pitch classes starting at C, then [dominant frequency in Hz, midi note, clarity (0..1), padding]
+    pub pitch: [[f32; 4]; 4],
+    // Per channel: [spectral centroid in Hz, flux, rolloff in Hz, flatness (0..1)]
+    pub features: Vec<[f32; 4]>,
+    // (mid, side) of every sample of the window, then the (left, right) vectorscope points
+    pub stereo: Vec<[f32; 2]>,
+    // [phase correlation (-1..1), width (0..1), vectorscope points, padding]
+    pub stereo_info: [f32; 4],
+    // Triggered oscilloscope window of every channel, one after the other
+    pub scope: Vec<f32>,
+    pub last_beat: Option<Instant>,
+    pub beat_count: u32,
+    pub onset_strength: f32,
+    // [bpm, beat phase (0..1), confidence (0..1)], the phase as it was at `tempo_time`
+    pub tempo: [f32; 3],
+    pub tempo_time: Instant,
+}
+
+/// Values per channel of the spectrum given to the shader, fft or constant-Q bins
+pub fn spectrum_bins(config: &AudioConfig) -> usize {
+    match config.cqt_bins_per_octave {
+        Some(bins_per_octave) => cqt::bin_count(
+            config.bands.min_freq,
+            config.bands.max_freq,
+            bins_per_octave,
+            config.sample_rate,
+        ),
+        None => config.buffer_size as usize / 2,
+    }
+}
+
+/// Samples per channel of the oscilloscope window, it can't be longer than the analysed window
+pub fn scope_samples(config: &AudioConfig) -> usize {
+    config.scope_samples.min(config.buffer_size) as usize
+}
+
+impl AudioFrame {
+    fn new(config: &AudioConfig) -> Self {
+        let buffer_size = config.buffer_size as usize;
+        AudioFrame {
+            audio_buffers: vec![vec![0.0; buffer_size]; config.channels],
+            fft_buffers: vec![vec![0.0; spectrum_bins(config)]; config.channels],
+            bands: vec![[0.0; 4]; config.bands.count],
+            levels: vec![[0.0; 4]; config.channels + 1],
+            pitch: [[0.0; 4]; 4],
+            features: vec![[0.0; 4]; config.channels],
+            stereo: vec![[0.0; 2]; stereo_buffer_len(buffer_size)],
+            stereo_info: [0.0; 4],
+            scope: vec![0.0; config.channels * scope_samples(config)],
+            last_beat: None,
+            beat_count: 0,
+            onset_strength: 0.0,
+            tempo: [0.0; 3],
+            tempo_time: Instant::now(),
+        }
+    }
+}
+
+/// Render side of the audio capture.
+/// The capture thread publishes every analysed window through a triple buffer,
+/// so the renderer always gets the newest one without ever waiting for a lock.
+pub struct AudioInput {
+    pub config: AudioConfig,
+    frames: Output<AudioFrame>,
+    // Cleared by the capture thread while the source is gone
+    available: Arc<AtomicBool>,
+    was_available: bool,
+    availability_changed: Instant,
+    // Tells the capture thread to exit, by being dropped
+    stop: Option<Sender<()>>,
+    // Waker of the source the capture thread is reading from, if it needs one
+    waker: Arc<Mutex<Option<Waker>>>,
+    thread: Option<JoinHandle<()>>,
 }
 
 impl AudioInput {
-    pub fn new(
-        device_name: Option<String>,
-        channels: usize,
-        sample_rate: u32,
-        buffer_size: u32,
+    /// Opens the source and starts capturing from it.
+    /// The source has the final say on the format, a wav file brings its own.
+    /// When it can't be opened, eg. the sound server is down, the capture starts unavailable
+    /// with the configured format, and keeps trying to open it.
+    pub fn start(mut config: AudioConfig, open: SourceOpener) -> Self {
+        let source = match open() {
+            Ok(source) => {
+                config.channels = source.channels();
+                config.sample_rate = source.sample_rate();
+                Some(source)
+            }
+            Err(err) => {
+                eprintln!("Unable to open audio source: {}", err);
+                None
+            }
+        };
+        Self::start_capture_loop(config, source, open)
+    }
+
+    /// Spawns the capture thread, reading from `source`.
+    /// When reading fails, or there is no source yet, `open` is called with an increasing delay
+    /// until a new source comes up.
+    pub fn start_capture_loop(
+        config: AudioConfig,
+        source: Option<Box<dyn AudioSource>>,
+        open: SourceOpener,
     ) -> Self {
+        let (input, frames) = TripleBuffer::new(&AudioFrame::new(&config)).split();
+        let available = Arc::new(AtomicBool::new(source.is_some()));
+        let capture_config = config.clone();
+        let capture_available = available.clone();
+        let (stop, capture_stop) = mpsc::channel();
+        let waker = Arc::new(Mutex::new(None));
+        let capture_waker = waker.clone();
+        let thread = thread::spawn(move || {
+            capture_loop(
+                capture_config,
+                source,
+                open,
+                input,
+                capture_available,
+                capture_stop,
+                capture_waker,
+            )
+        });
         AudioInput {
-            device_name,
-            channels,
-            sample_rate,
-            buffer_size,
-            used: true,
-            audio_buffers: vec![],
-            fft_buffers: vec![],
+            config,
+            frames,
+            was_available: available.load(Ordering::Relaxed),
+            available,
+            availability_changed: Instant::now(),
+            stop: Some(stop),
+            waker,
+            thread: Some(thread),
         }
     }
 
-    pub fn start_capture_loop(audio_in: Arc<Mutex<AudioInput>>) {
-        let ai = audio_in.lock().unwrap();
-        let n_channels = ai.channels;
-        let buffer_size = ai.buffer_size;
-        let sample_rate = ai.sample_rate;
-        let spec = Spec {
-            format: Format::FLOAT32NE,
-            channels: n_channels as u8,
-            rate: sample_rate,
-        };
+    /// Whether the source is currently delivering samples, and for how long that has been the case
+    pub fn availability(&mut self) -> (bool, Duration) {
+        let available = self.available.load(Ordering::Relaxed);
+        if available != self.was_available {
+            self.was_available = available;
+            self.availability_changed = Instant::now();
+        }
+        (available, self.availability_changed.elapsed())
+    }
 
-        let attr = BufferAttr {
-            maxlength: std::u32::MAX,
-            fragsize: buffer_size,
-            ..Default::default()
-        };
+    /// The newest frame, None if nothing new was published since the last call
+    pub fn new_frame(&mut self) -> Option<&AudioFrame> {
+        if self.frames.update() {
+            Some(self.frames.output_buffer())
+        } else {
+            None
+        }
+    }
 
-        assert!(spec.is_valid());
-        let s = Simple::new(
-            None,                      // Use the default server
-            "wgpu-paper",              // Our application’s name
-            Direction::Record,         // We want a playback stream
-            ai.device_name.as_deref(), // Use the default device
-            "Music",                   // Description of our stream
-            &spec,                     // Our sample format
-            None,                      // Use default channel map
-            Some(&attr),               // Use default buffering attributes
-        )
-        .expect("Error opening audio stream");
+    /// The last frame returned by `new_frame`
+    pub fn frame(&mut self) -> &AudioFrame {
+        self.frames.output_buffer()
+    }
+}
 
-        drop(ai);
+impl Drop for AudioInput {
+    // Waits for the thread to close the source, so a new capture never reads next to the old one.
+    // The thread notices after its current read, the waker cuts short a read waiting for data that may never come
+    fn drop(&mut self) {
+        self.stop = None;
+        if let Some(waker) = &*self.waker.lock().unwrap() {
+            waker();
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
 
-        let mut planner = FftPlanner::<f32>::new();
-        loop {
-            // If the buffers haven't been used yet, don't waste cpu time
-            // This is suboptimal, since it adds latency, but it should be acceptable given high enough fps
+fn capture_loop(
+    config: AudioConfig,
+    mut source: Option<Box<dyn AudioSource>>,
+    open: SourceOpener,
+    mut frames: Input<AudioFrame>,
+    available: Arc<AtomicBool>,
+    stop: Receiver<()>,
+    waker: Arc<Mutex<Option<Waker>>>,
+) {
+    let n_channels = config.channels;
+    let buffer_size = config.buffer_size as usize;
+    let hop_size = config.hop_size as usize;
+    let sample_rate = config.sample_rate;
+    let window = config.window.coefficients(buffer_size);
+    let band_aggregator = BandAggregator::new(&config.bands, sample_rate, buffer_size);
+    // Every hop gets analysed, so the time between two windows is always the same
+    let dt = hop_size as f32 / sample_rate as f32;
+    // Compare the flux to roughly the last second of audio
+    let mut onset_detector = OnsetDetector::new(
+        band_aggregator.count(),
+        sample_rate as usize / hop_size,
+        config.beat_sensitivity,
+    );
+    let mut scope = Scope::new(config.scope_trigger, scope_samples(&config));
+    let mut spectral_features = SpectralFeatures::new(n_channels, sample_rate, buffer_size);
+    let mut pitch_detector = PitchDetector::new(sample_rate, buffer_size);
+    let mut chroma = [0.0f32; 12];
+    let mut level_meter = LevelMeter::new(n_channels, sample_rate, hop_size);
+    let mut tempo_tracker = TempoTracker::new(1.0 / dt);
+    let bars_export = config.bars_output.clone().map(BarsExport::start);
+    let mut band_smoother = Smoother::new(band_aggregator.count(), config.attack, config.release);
+    let mut spectrum_smoothers: Vec<Smoother> = (0..n_channels)
+        .map(|_| Smoother::new(spectrum_bins(&config), config.attack, config.release))
+        .collect();
+    let mut waveform_gain = config.agc_window.map(AutoGain::new);
+    let mut spectrum_gain = config.agc_window.map(AutoGain::new);
+    let mut cqt_gain = config.agc_window.map(AutoGain::new);
+    let constant_q = config.cqt_bins_per_octave.map(|bins_per_octave| {
+        ConstantQ::new(
+            sample_rate,
+            buffer_size,
+            config.bands.min_freq,
+            config.bands.max_freq,
+            bins_per_octave,
+        )
+    });
 
-            while !audio_in.lock().unwrap().used {
-                std::thread::sleep(Duration::from_millis(
-                    sample_rate as u64 / buffer_size as u64,
-                ));
+    // Plan once, and keep all the working memory around between windows
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(buffer_size);
+    let mut fft_scratch = vec![Complex::default(); fft.get_inplace_scratch_len()];
+    let mut fft_cmplx_buffer = vec![Complex::default(); buffer_size];
+    let mut mono_spectrum = vec![0.0f32; buffer_size / 2];
+    // The linear spectrum, when the shader gets the constant-Q one
+    let mut linear_spectra =
+        vec![vec![0.0f32; buffer_size / 2]; constant_q.as_ref().map_or(0, |_| n_channels)];
+    let mut band_values = vec![0.0f32; band_aggregator.count()];
+    let mut band_flux = vec![0.0f32; band_aggregator.count()];
+    let mut band_onsets = vec![false; band_aggregator.count()];
+    let mut last_beat = None;
+    let mut beat_count = 0;
+    // The last buffer_size samples of every channel, the newest ones at the end
+    let mut history = vec![vec![0.0f32; buffer_size]; n_channels];
+    let mut data = vec![0.0f32; hop_size * n_channels];
+    let mut backoff = MIN_BACKOFF;
+    // Set before the stop gets checked, so stopping either sees the waker or the loop sees the stop
+    let set_waker = |source: &Option<Box<dyn AudioSource>>| {
+        *waker.lock().unwrap() = source.as_ref().and_then(|source| source.waker());
+    };
+    set_waker(&source);
+    while let Err(TryRecvError::Empty) = stop.try_recv() {
+        let Some(current) = &mut source else {
+            // Waits, unless the capture gets stopped meanwhile
+            if let Ok(()) | Err(RecvTimeoutError::Disconnected) = stop.recv_timeout(backoff) {
+                break;
             }
-
-            // Read data from pulseaudio
-            let mut d_binding =
-                vec![0; buffer_size as usize * std::mem::size_of::<f32>() * n_channels];
-            let d = d_binding.as_mut_slice();
-
-            s.read(d).unwrap();
-
-            // Convert the byte array into f32
-            let data: Vec<f32> = d
-                .chunks(4)
-                .map(|c| f32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
-                .collect();
-            // The data is stored this way:
-            // sample = [ channel-0 , channel-1, ... channel-n]
-            let samples: Vec<&[f32]> = data.chunks(n_channels).collect();
-            dbg!(samples.len());
-            let fft = planner.plan_fft_forward(samples.len());
-            let mut audio_buffers = vec![vec![]; n_channels];
-            for sample in samples.iter() {
-                for (channel, amplitude) in sample.iter().enumerate() {
-                    audio_buffers[channel].push(*amplitude);
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            match open() {
+                Ok(new) if new.channels() != n_channels || new.sample_rate() != sample_rate => {
+                    let err = AudioError::FormatChanged {
+                        channels: new.channels(),
+                        sample_rate: new.sample_rate(),
+                    };
+                    eprintln!("Unable to resume audio capture: {}", err);
+                }
+                Ok(new) => {
+                    source = Some(new);
+                    set_waker(&source);
                 }
+                Err(err) => eprintln!("Unable to resume audio capture: {}", err),
+            }
+            continue;
+        };
+        if let Err(err) = current.read(&mut data) {
+            // Woken up to stop, not an actual failure
+            if let Ok(()) | Err(TryRecvError::Disconnected) = stop.try_recv() {
+                break;
+            }
+            if available.swap(false, Ordering::Relaxed) {
+                eprintln!("Audio capture stopped: {}", err);
+            } else {
+                eprintln!("Unable to resume audio capture: {}", err);
             }
+            source = None;
+            set_waker(&source);
+            continue;
+        }
+        // Only a source that actually delivers counts as back
+        backoff = MIN_BACKOFF;
+        if !available.swap(true, Ordering::Relaxed) {
+            eprintln!("Audio capture resumed");
+        }
+
+        // Slide the window of every channel forward by the new samples
+        for (channel, wave) in history.iter_mut().enumerate() {
+            wave.extend(data.iter().skip(channel).step_by(n_channels));
+            let excess = wave.len() - buffer_size;
+            wave.drain(..excess);
+        }
+
+        // The back buffer still holds an old frame, everything in it gets overwritten
+        let frame = frames.input_buffer();
+        for (wave, history) in frame.audio_buffers.iter_mut().zip(&history) {
+            wave.copy_from_slice(history);
+        }
+        // Measured before the gain control, so the levels follow the real volume
+        level_meter.process(&data, dt, &mut frame.levels);
+        if let Some(waveform_gain) = &mut waveform_gain {
+            waveform_gain.process(&mut frame.audio_buffers, dt);
+        }
 
-            // f32 -> cmplx numbers
-            let mut fft_cpmlx_buffers = vec![vec![]; n_channels];
-            for (channel, wave) in audio_buffers.iter().enumerate() {
-                fft_cpmlx_buffers[channel] = wave
-                    .iter()
-                    .map(|n| Complex { re: *n, im: 0.0f32 })
-                    .collect();
-                fft.process(&mut fft_cpmlx_buffers[channel]);
+        frame.stereo_info = stereo::process(&frame.audio_buffers, &mut frame.stereo);
+        scope.process(&frame.audio_buffers, &mut frame.scope);
+
+        if config.gpu_fft {
+            frames.publish();
+            continue;
+        }
+
+        // The analysis always runs on the linear spectrum
+        let linear = match constant_q {
+            Some(_) => &mut linear_spectra,
+            None => &mut frame.fft_buffers,
+        };
+
+        // Calculate fft, with the window applied
+        for (wave, fft_buffer) in frame.audio_buffers.iter().zip(linear.iter_mut()) {
+            for ((cmplx, n), w) in fft_cmplx_buffer.iter_mut().zip(wave).zip(&window) {
+                *cmplx = Complex { re: n * w, im: 0.0 };
+            }
+            fft.process_with_scratch(&mut fft_cmplx_buffer, &mut fft_scratch);
+            for (magnitude, cmplx) in fft_buffer.iter_mut().zip(&fft_cmplx_buffer) {
+                *magnitude = cmplx.norm();
             }
+        }
+        spectral_features.process(linear, &mut frame.features);
+        if let Some(spectrum_gain) = &mut spectrum_gain {
+            spectrum_gain.process(linear, dt);
+        }
 
-            // Calculate fft
-            let mut fft_buffers = vec![vec![]; n_channels];
-            for (channel, cmplx_fft) in fft_cpmlx_buffers.iter().enumerate() {
-                fft_buffers[channel] = cmplx_fft
-                    .iter()
-                    .take(samples.len() / 2)
-                    .map(|c| c.norm())
-                    .collect();
+        // Mix the channels down before splitting the spectrum into bands
+        mono_spectrum.fill(0.0);
+        for fft in linear.iter() {
+            for (mono, magnitude) in mono_spectrum.iter_mut().zip(fft) {
+                *mono += magnitude / n_channels as f32;
             }
+        }
+        band_aggregator.aggregate(&mono_spectrum, &mut band_values);
+        let (onset_strength, beat) =
+            onset_detector.process(&band_values, &mut band_flux, &mut band_onsets);
+        let (pitch, clarity) = pitch_detector.process(&mono_spectrum, &mut chroma);
+        for (vec, classes) in frame.pitch.iter_mut().zip(chroma.chunks_exact(4)) {
+            vec.copy_from_slice(classes);
+        }
+        frame.pitch[3] = [
+            pitch,
+            if pitch > 0.0 {
+                pitch::midi_note(pitch)
+            } else {
+                0.0
+            },
+            clarity,
+            0.0,
+        ];
 
-            // Swap buffers
+        // The kernels bring their own windows, so this takes another fft of the plain waveform
+        if let Some(constant_q) = &constant_q {
+            for (wave, cqt) in frame.audio_buffers.iter().zip(frame.fft_buffers.iter_mut()) {
+                for (cmplx, n) in fft_cmplx_buffer.iter_mut().zip(wave) {
+                    *cmplx = Complex { re: *n, im: 0.0 };
+                }
+                fft.process_with_scratch(&mut fft_cmplx_buffer, &mut fft_scratch);
+                constant_q.process(&fft_cmplx_buffer, cqt);
+            }
+            if let Some(cqt_gain) = &mut cqt_gain {
+                cqt_gain.process(&mut frame.fft_buffers, dt);
+            }
+        }
+
+        // Smooth only after the onset detection, smoothing would flatten the flux
+        band_smoother.process(&mut band_values, dt);
+        for (smoother, fft) in spectrum_smoothers
+            .iter_mut()
+            .zip(frame.fft_buffers.iter_mut())
+        {
+            smoother.process(fft, dt);
+        }
+
+        for (band, value) in frame.bands.iter_mut().enumerate() {
+            value[0] = band_values[band];
+            value[1] = if band_onsets[band] { 1.0 } else { 0.0 };
+            value[2] = band_flux[band];
+        }
+        if let Some(bars_export) = &bars_export {
+            bars_export.send(&band_values);
+        }
+        if beat {
+            last_beat = Some(Instant::now());
+            beat_count += 1;
+        }
+        frame.last_beat = last_beat;
+        frame.beat_count = beat_count;
+        frame.onset_strength = onset_strength;
+        frame.tempo = tempo_tracker.process(onset_strength, beat, dt);
+        frame.tempo_time = Instant::now();
+
+        frames.publish();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use hound::{SampleFormat, WavSpec, WavWriter};
+    use std::f32::consts::PI;
+
+    const SAMPLE_RATE: u32 = 44100;
+    const BUFFER_SIZE: u32 = 1024;
 
-            let mut ai = audio_in.lock().unwrap();
-            ai.audio_buffers = audio_buffers;
-            ai.fft_buffers = fft_buffers;
-            ai.used = false;
-            //dbg!(ai.fft_buffers[0]
-            //    .iter()
-            //    .enumerate()
-            //    .max_by(|a, b| a.1.total_cmp(b.1)));
+    pub(crate) fn config() -> AudioConfig {
+        AudioConfig {
+            channels: 2,
+            sample_rate: SAMPLE_RATE,
+            buffer_size: BUFFER_SIZE,
+            hop_size: 512,
+            window: WindowFunction::Hann,
+            gpu_fft: false,
+            cqt_bins_per_octave: None,
+            spectrogram_rows: 16,
+            scope_samples: 256,
+            scope_trigger: ScopeTrigger::Rising,
+            bands: BandConfig {
+                scale: BandScale::Log,
+                count: 8,
+                min_freq: 50.0,
+                max_freq: 10000.0,
+            },
+            beat_sensitivity: 1.5,
+            attack: 0.0,
+            release: 0.0,
+            agc_window: None,
+            bars_output: None,
         }
     }
+
+    #[test]
+    fn wav_file_through_the_capture_loop() {
+        // A second of a 1 kHz sine, at half scale on the left and a quarter on the right
+        let path =
+            std::env::temp_dir().join(format!("wgpu-paper-capture-{}.wav", std::process::id()));
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let mut writer = WavWriter::create(&path, spec).unwrap();
+        for n in 0..SAMPLE_RATE {
+            let sample = 0.5 * (2.0 * PI * 1000.0 * n as f32 / SAMPLE_RATE as f32).sin();
+            writer.write_sample(sample).unwrap();
+            writer.write_sample(sample / 2.0).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let wav_path = path.clone();
+        let open: SourceOpener =
+            Arc::new(move || Ok(Box::new(WavSource::open(&wav_path)?) as Box<dyn AudioSource>));
+        let mut input = AudioInput::start(config(), open);
+        // The source is paced in real time, this is enough to fill the window several times
+        thread::sleep(Duration::from_millis(300));
+        assert!(input.availability().0);
+        let frame = input.new_frame().expect("no frame was published");
+
+        assert_eq!(frame.audio_buffers.len(), 2);
+        for (left, right) in frame.audio_buffers[0].iter().zip(&frame.audio_buffers[1]) {
+            assert_eq!(*left / 2.0, *right);
+        }
+        // 1 kHz falls into bin 23 of a 1024 sample fft at 44.1 kHz
+        let peak = (0..frame.fft_buffers[0].len())
+            .max_by(|a, b| frame.fft_buffers[0][*a].total_cmp(&frame.fft_buffers[0][*b]))
+            .unwrap();
+        assert_eq!(peak, 23);
+        // rms of a sine is its amplitude over sqrt(2)
+        let rms = frame.levels[1][0];
+        assert!((rms - 0.5 / 2f32.sqrt()).abs() < 0.01, "rms {}", rms);
+        // 1 kHz is closest to B5, the last of the 12 pitch classes
+        assert_eq!(frame.pitch[2][3], 1.0);
+        // Log spaced, 50 Hz to 10 kHz in 8 bands puts 1 kHz into band 4
+        let loudest = (0..frame.bands.len())
+            .max_by(|a, b| frame.bands[*a][0].total_cmp(&frame.bands[*b][0]))
+            .unwrap();
+        assert_eq!(loudest, 4);
+        // Both channels are in phase
+        assert!((frame.stereo_info[0] - 1.0).abs() < 1e-3);
+        assert_eq!(frame.scope.len(), 2 * 256);
+
+        drop(input);
+        std::fs::remove_file(&path).unwrap();
+    }
 }
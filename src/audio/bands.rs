@@ -0,0 +1,147 @@
+/// How the band edges are spread between the minimum and the maximum frequency
+#[derive(Clone, Copy)]
+pub enum BandScale {
+    Log,
+    Mel,
+}
+
+#[derive(Clone)]
+pub struct BandConfig {
+    pub scale: BandScale,
+    pub count: usize,
+    pub min_freq: f32,
+    pub max_freq: f32,
+}
+
+fn hz_to_mel(hz: f32) -> f32 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+
+fn mel_to_hz(mel: f32) -> f32 {
+    700.0 * (10f32.powf(mel / 2595.0) - 1.0)
+}
+
+/// Sums up the linear fft bins into a small number of perceptually spaced bands
+pub struct BandAggregator {
+    // Fractional bin range covered by every band
+    ranges: Vec<(f32, f32)>,
+}
+
+impl BandAggregator {
+    pub fn new(config: &BandConfig, sample_rate: u32, fft_size: usize) -> Self {
+        let nyquist = sample_rate as f32 / 2.0;
+        let max_freq = config.max_freq.min(nyquist);
+        // Not a clamp, the nyquist frequency can be below the minimum
+        let min_freq = config.min_freq.max(1.0).min(max_freq);
+        let bin_width = sample_rate as f32 / fft_size as f32;
+
+        let edge = |i: usize| {
+            let t = i as f32 / config.count as f32;
+            match config.scale {
+                BandScale::Log => min_freq * (max_freq / min_freq).powf(t),
+                BandScale::Mel => {
+                    let (min_mel, max_mel) = (hz_to_mel(min_freq), hz_to_mel(max_freq));
+                    mel_to_hz(min_mel + (max_mel - min_mel) * t)
+                }
+            }
+        };
+
+        let ranges = (0..config.count)
+            .map(|i| (edge(i) / bin_width, edge(i + 1) / bin_width))
+            .collect();
+        BandAggregator { ranges }
+    }
+
+    pub fn count(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Writes the strongest magnitude of every band into `out`,
+    /// so the wide high bands don't get diluted by their many quiet bins
+    pub fn aggregate(&self, spectrum: &[f32], out: &mut [f32]) {
+        if spectrum.is_empty() {
+            out.fill(0.0);
+            return;
+        }
+        let last = spectrum.len() - 1;
+        for (value, (start, end)) in out.iter_mut().zip(&self.ranges) {
+            let first_bin = (start.round() as usize).min(last);
+            let last_bin = (end.round() as usize).min(last + 1);
+            *value = if last_bin > first_bin {
+                spectrum[first_bin..last_bin]
+                    .iter()
+                    .fold(0.0f32, |max, v| max.max(*v))
+            } else {
+                // The low bands can be narrower than a single bin, interpolate between the closest ones
+                let center = ((start + end) / 2.0).min(last as f32);
+                let bin = center.floor() as usize;
+                let next = (bin + 1).min(last);
+                let t = center - bin as f32;
+                spectrum[bin] * (1.0 - t) + spectrum[next] * t
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(scale: BandScale, count: usize, min_freq: f32, max_freq: f32) -> BandConfig {
+        BandConfig {
+            scale,
+            count,
+            min_freq,
+            max_freq,
+        }
+    }
+
+    #[test]
+    fn log_bands_double_in_width() {
+        // 100 Hz per bin
+        let aggregator = BandAggregator::new(&config(BandScale::Log, 3, 100.0, 800.0), 1600, 16);
+        for (range, expected) in aggregator
+            .ranges
+            .iter()
+            .zip([(1.0, 2.0), (2.0, 4.0), (4.0, 8.0)])
+        {
+            assert!((range.0 - expected.0).abs() < 1e-4 && (range.1 - expected.1).abs() < 1e-4);
+        }
+
+        let spectrum = [9.0, 1.0, 2.0, 3.0, 4.0, 8.0, 6.0, 7.0];
+        let mut bands = [0.0; 3];
+        aggregator.aggregate(&spectrum, &mut bands);
+        assert_eq!(bands, [1.0, 3.0, 8.0]);
+    }
+
+    #[test]
+    fn mel_bands_cover_the_range() {
+        let aggregator =
+            BandAggregator::new(&config(BandScale::Mel, 10, 50.0, 10000.0), 44100, 4096);
+        let bin_width = 44100.0 / 4096.0;
+        assert!((aggregator.ranges[0].0 * bin_width - 50.0).abs() < 0.01);
+        assert!((aggregator.ranges[9].1 * bin_width - 10000.0).abs() < 1.0);
+        for pair in aggregator.ranges.windows(2) {
+            assert!((pair[0].1 - pair[1].0).abs() < 1e-3);
+            // Wider and wider, like the log scale
+            assert!(pair[1].1 - pair[1].0 > pair[0].1 - pair[0].0);
+        }
+    }
+
+    #[test]
+    fn narrow_bands_interpolate() {
+        // A single band from 10 to 20 Hz, a tenth of a bin
+        let aggregator = BandAggregator::new(&config(BandScale::Log, 1, 10.0, 20.0), 1600, 16);
+        let mut bands = [0.0];
+        aggregator.aggregate(&[1.0, 2.0, 0.0, 0.0], &mut bands);
+        assert!((bands[0] - 1.15).abs() < 1e-4);
+    }
+
+    #[test]
+    fn frequencies_below_the_minimum_are_clamped() {
+        let aggregator = BandAggregator::new(&config(BandScale::Log, 4, 50.0, 0.5), 44100, 1024);
+        let mut bands = [1.0; 4];
+        aggregator.aggregate(&[0.0; 512], &mut bands);
+        assert_eq!(bands, [0.0; 4]);
+    }
+}
@@ -0,0 +1,110 @@
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+
+// Kernel values below this fraction of the kernel's peak are dropped, it keeps the kernels sparse
+const KERNEL_THRESHOLD: f32 = 0.01;
+
+/// Number of constant-Q bins between `min_freq` and `max_freq`, capped at the nyquist frequency
+pub fn bin_count(min_freq: f32, max_freq: f32, bins_per_octave: u32, sample_rate: u32) -> usize {
+    let max_freq = max_freq.min(sample_rate as f32 / 2.0);
+    ((bins_per_octave as f32 * (max_freq / min_freq).log2()).ceil() as usize).max(1)
+}
+
+/// Constant-Q transform computed from an fft, with a precomputed sparse kernel per bin.
+/// The bins are spaced logarithmically, every one of them `1 / bins_per_octave` octave wide.
+/// Bins that would need a longer window than the fft get a wider bandwidth instead.
+pub struct ConstantQ {
+    // (fft bin, kernel value) pairs of every constant-Q bin
+    kernels: Vec<Vec<(usize, Complex<f32>)>>,
+}
+
+impl ConstantQ {
+    pub fn new(
+        sample_rate: u32,
+        fft_size: usize,
+        min_freq: f32,
+        max_freq: f32,
+        bins_per_octave: u32,
+    ) -> Self {
+        let q = 1.0 / (2f32.powf(1.0 / bins_per_octave as f32) - 1.0);
+        let count = bin_count(min_freq, max_freq, bins_per_octave, sample_rate);
+        let fft = FftPlanner::<f32>::new().plan_fft_forward(fft_size);
+
+        let kernels = (0..count)
+            .map(|bin| {
+                let freq = min_freq * 2f32.powf(bin as f32 / bins_per_octave as f32);
+                let length = ((q * sample_rate as f32 / freq).ceil() as usize).clamp(1, fft_size);
+
+                // Hann windowed complex sinusoid, centered in the frame
+                let mut kernel = vec![Complex::default(); fft_size];
+                let start = (fft_size - length) / 2;
+                for n in 0..length {
+                    let window =
+                        0.5 - 0.5 * (std::f32::consts::TAU * n as f32 / length as f32).cos();
+                    let phase = std::f32::consts::TAU * freq * n as f32 / sample_rate as f32;
+                    kernel[start + n] = Complex::from_polar(window / length as f32, phase);
+                }
+                fft.process(&mut kernel);
+
+                let peak = kernel.iter().fold(0.0f32, |max, k| max.max(k.norm()));
+                kernel
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, k)| k.norm() >= KERNEL_THRESHOLD * peak)
+                    .map(|(i, k)| (i, k.conj() / fft_size as f32))
+                    .collect()
+            })
+            .collect();
+
+        ConstantQ { kernels }
+    }
+
+    /// `spectrum` is the complex fft of an unwindowed frame, the window is part of the kernels
+    pub fn process(&self, spectrum: &[Complex<f32>], out: &mut [f32]) {
+        for (magnitude, kernel) in out.iter_mut().zip(&self.kernels) {
+            *magnitude = kernel
+                .iter()
+                .map(|(i, k)| spectrum[*i] * k)
+                .sum::<Complex<f32>>()
+                .norm();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bins_per_octave() {
+        assert_eq!(bin_count(55.0, 880.0, 12, 44100), 48);
+        assert_eq!(bin_count(55.0, 880.0, 24, 44100), 96);
+        // Capped at the nyquist frequency, 500 Hz
+        assert_eq!(bin_count(125.0, 4000.0, 1, 1000), 2);
+    }
+
+    #[test]
+    fn sine_peaks_in_its_bin() {
+        let (sample_rate, fft_size) = (44100, 8192);
+        let constant_q = ConstantQ::new(sample_rate, fft_size, 110.0, 1760.0, 12);
+        let fft = FftPlanner::<f32>::new().plan_fft_forward(fft_size);
+        // 440 Hz is two octaves above the lowest bin
+        for (freq, expected) in [(440.0, 24), (261.63, 15)] {
+            let mut spectrum: Vec<_> = (0..fft_size)
+                .map(|n| Complex {
+                    re: (std::f32::consts::TAU * freq * n as f32 / sample_rate as f32).sin(),
+                    im: 0.0,
+                })
+                .collect();
+            fft.process(&mut spectrum);
+            let mut bins = vec![0.0; bin_count(110.0, 1760.0, 12, sample_rate)];
+            constant_q.process(&spectrum, &mut bins);
+            let peak = (0..bins.len())
+                .max_by(|a, b| bins[*a].total_cmp(&bins[*b]))
+                .unwrap();
+            assert_eq!(peak, expected);
+            // About half the amplitude of the sine, like a Hann windowed dft
+            assert!((bins[peak] - 0.25).abs() < 0.05, "{}", bins[peak]);
+        }
+    }
+}
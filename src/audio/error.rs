@@ -0,0 +1,54 @@
+use std::{error::Error, fmt, io};
+
+use pulse::error::PAErr;
+
+/// Why an audio source couldn't be opened, or stopped producing samples
+#[derive(Debug)]
+pub enum AudioError {
+    Pulse(PAErr),
+    Io(io::Error),
+    Wav(hound::Error),
+    // The other end went away, eg. stdin was closed or the PipeWire node disappeared
+    Disconnected(String),
+    // A reopened source doesn't produce what the buffers were sized for
+    FormatChanged { channels: usize, sample_rate: u32 },
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AudioError::Pulse(err) => write!(f, "PulseAudio: {}", err),
+            AudioError::Io(err) => write!(f, "{}", err),
+            AudioError::Wav(err) => write!(f, "wav: {}", err),
+            AudioError::Disconnected(reason) => write!(f, "{}", reason),
+            AudioError::FormatChanged {
+                channels,
+                sample_rate,
+            } => write!(
+                f,
+                "the source now records {} channels at {} Hz",
+                channels, sample_rate
+            ),
+        }
+    }
+}
+
+impl Error for AudioError {}
+
+impl From<PAErr> for AudioError {
+    fn from(err: PAErr) -> Self {
+        AudioError::Pulse(err)
+    }
+}
+
+impl From<io::Error> for AudioError {
+    fn from(err: io::Error) -> Self {
+        AudioError::Io(err)
+    }
+}
+
+impl From<hound::Error> for AudioError {
+    fn from(err: hound::Error) -> Self {
+        AudioError::Wav(err)
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread,
+};
+
+// Highest value of the ascii format, like the raw output of cava
+const ASCII_MAX: f32 = 1000.0;
+
+/// How the bar values are written
+#[derive(Clone, Copy)]
+pub enum BarsFormat {
+    // Integers from 0 to 1000 separated by ';', one line per update
+    Ascii,
+    // Little endian u16 from 0 to 65535, the bars of one update after the other
+    Binary,
+}
+
+#[derive(Clone)]
+pub struct BarsOutput {
+    // File or named pipe, - for stdout
+    pub path: PathBuf,
+    pub format: BarsFormat,
+}
+
+/// Hands the band values of every update to a thread that writes them out.
+/// The capture never waits for the reader, updates are dropped while the writer is behind.
+pub struct BarsExport {
+    sender: SyncSender<Vec<f32>>,
+}
+
+impl BarsExport {
+    pub fn start(output: BarsOutput) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        thread::spawn(move || write_loop(output, receiver));
+        BarsExport { sender }
+    }
+
+    pub fn send(&self, bars: &[f32]) {
+        // Full means the writer is behind, disconnected that it gave up after reporting why
+        let _ = self.sender.try_send(bars.to_vec());
+    }
+}
+
+fn write_loop(output: BarsOutput, receiver: Receiver<Vec<f32>>) {
+    let path = (output.path.as_os_str() != "-").then_some(output.path);
+    let mut line = String::new();
+    loop {
+        // Opening a named pipe blocks until somebody reads it
+        let writer: Box<dyn Write> = match &path {
+            Some(path) => match OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)
+            {
+                Ok(file) => Box::new(file),
+                Err(err) => {
+                    eprintln!("Unable to open {} for the bars: {}", path.display(), err);
+                    return;
+                }
+            },
+            None => Box::new(io::stdout()),
+        };
+
+        // Skip what piled up while waiting for the reader
+        while receiver.try_recv().is_ok() {}
+
+        let Err(err) = write_bars(
+            &mut BufWriter::new(writer),
+            output.format,
+            &receiver,
+            &mut line,
+        ) else {
+            // The capture thread is gone
+            return;
+        };
+        match &path {
+            // Wait for the next reader of the pipe
+            Some(_) if err.kind() == io::ErrorKind::BrokenPipe => {}
+            _ => {
+                eprintln!("Unable to write the bars: {}", err);
+                return;
+            }
+        }
+    }
+}
+
+// Writes every update until the capture thread is gone, or writing fails
+fn write_bars(
+    writer: &mut impl Write,
+    format: BarsFormat,
+    receiver: &Receiver<Vec<f32>>,
+    line: &mut String,
+) -> io::Result<()> {
+    for bars in receiver.iter() {
+        match format {
+            BarsFormat::Ascii => {
+                line.clear();
+                for (i, bar) in bars.iter().enumerate() {
+                    if i > 0 {
+                        line.push(';');
+                    }
+                    *line += &((bar.clamp(0.0, 1.0) * ASCII_MAX).round() as u32).to_string();
+                }
+                line.push('\n');
+                writer.write_all(line.as_bytes())?;
+            }
+            BarsFormat::Binary => {
+                for bar in &bars {
+                    let value = (bar.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16;
+                    writer.write_all(&value.to_le_bytes())?;
+                }
+            }
+        }
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn written(format: BarsFormat, updates: &[&[f32]]) -> Vec<u8> {
+        let (sender, receiver) = mpsc::sync_channel(updates.len());
+        for bars in updates {
+            sender.send(bars.to_vec()).unwrap();
+        }
+        drop(sender);
+        let mut out = vec![];
+        write_bars(&mut out, format, &receiver, &mut String::new()).unwrap();
+        out
+    }
+
+    #[test]
+    fn ascii_lines() {
+        let out = written(BarsFormat::Ascii, &[&[0.0, 0.5, 1.5], &[0.25]]);
+        assert_eq!(String::from_utf8(out).unwrap(), "0;500;1000\n250\n");
+    }
+
+    #[test]
+    fn binary_values() {
+        let out = written(BarsFormat::Binary, &[&[0.0, 1.0], &[-1.0]]);
+        assert_eq!(out, [0, 0, 255, 255, 0, 0]);
+    }
+
+    #[test]
+    fn creates_a_plain_file() {
+        let path = std::env::temp_dir().join(format!("wgpu-paper-bars-{}", std::process::id()));
+        let export = BarsExport::start(BarsOutput {
+            path: path.clone(),
+            format: BarsFormat::Ascii,
+        });
+        // Updates sent before the file is open get skipped
+        let start = Instant::now();
+        while std::fs::read_to_string(&path).map_or(true, |bars| bars.is_empty()) {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            export.send(&[1.0, 0.0]);
+            thread::sleep(Duration::from_millis(10));
+        }
+        drop(export);
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("1000;0\n"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
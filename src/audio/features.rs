@@ -0,0 +1,117 @@
+// Share of the spectral energy below the rolloff frequency
+const ROLLOFF: f32 = 0.85;
+// Keeps silent bins from sending the geometric mean of the flatness to zero
+const MIN_POWER: f32 = 1e-10;
+
+/// Single number descriptors of the spectrum of every channel.
+/// All of them are independent of the volume, so the gain control doesn't change them.
+pub struct SpectralFeatures {
+    bin_width: f32,
+    previous: Vec<Vec<f32>>,
+}
+
+impl SpectralFeatures {
+    pub fn new(channels: usize, sample_rate: u32, fft_size: usize) -> Self {
+        SpectralFeatures {
+            bin_width: sample_rate as f32 / fft_size as f32,
+            previous: vec![vec![0.0; fft_size / 2]; channels],
+        }
+    }
+
+    /// Writes [centroid in Hz, flux, rolloff in Hz, flatness (0..1)] of every channel to `features`.
+    /// The flux is the rise in magnitude since the last call, relative to the current magnitude.
+    pub fn process(&mut self, spectra: &[Vec<f32>], features: &mut [[f32; 4]]) {
+        for ((spectrum, previous), out) in spectra
+            .iter()
+            .zip(self.previous.iter_mut())
+            .zip(features.iter_mut())
+        {
+            // The DC bin says nothing about the sound
+            let bins = spectrum.iter().zip(previous.iter()).enumerate().skip(1);
+
+            let mut magnitude_sum = 0.0f32;
+            let mut weighted_sum = 0.0f32;
+            let mut power_sum = 0.0f32;
+            let mut log_power_sum = 0.0f32;
+            let mut rise = 0.0f32;
+            for (bin, (magnitude, previous)) in bins {
+                magnitude_sum += magnitude;
+                weighted_sum += magnitude * bin as f32;
+                let power = magnitude * magnitude;
+                power_sum += power;
+                log_power_sum += power.max(MIN_POWER).ln();
+                rise += (magnitude - previous).max(0.0);
+            }
+            previous.copy_from_slice(spectrum);
+
+            if magnitude_sum <= f32::EPSILON {
+                *out = [0.0; 4];
+                continue;
+            }
+            let count = (spectrum.len() - 1) as f32;
+
+            let mut energy = 0.0;
+            let rolloff_bin = spectrum
+                .iter()
+                .enumerate()
+                .skip(1)
+                .find(|(_, magnitude)| {
+                    energy += *magnitude * *magnitude;
+                    energy >= ROLLOFF * power_sum
+                })
+                .map_or(spectrum.len() - 1, |(bin, _)| bin);
+
+            *out = [
+                weighted_sum / magnitude_sum * self.bin_width,
+                rise / magnitude_sum,
+                rolloff_bin as f32 * self.bin_width,
+                // Geometric over arithmetic mean of the power, high for noise and near 0 for a pure tone
+                ((log_power_sum / count).exp() / (power_sum / count)).min(1.0),
+            ];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_bin() {
+        // 10 Hz per bin
+        let mut features = SpectralFeatures::new(1, 1000, 100);
+        let mut spectrum = vec![0.0; 50];
+        spectrum[20] = 1.0;
+        let mut out = [[0.0; 4]];
+        features.process(&[spectrum.clone()], &mut out);
+        let [centroid, flux, rolloff, flatness] = out[0];
+        assert!((centroid - 200.0).abs() < 1e-3);
+        assert!((flux - 1.0).abs() < 1e-6);
+        assert!((rolloff - 200.0).abs() < 1e-3);
+        assert!(flatness < 1e-3);
+
+        // Nothing changed since the last call
+        features.process(&[spectrum], &mut out);
+        assert_eq!(out[0][1], 0.0);
+    }
+
+    #[test]
+    fn flat_spectrum() {
+        let mut features = SpectralFeatures::new(1, 1000, 100);
+        let mut out = [[0.0; 4]];
+        features.process(&[vec![1.0; 50]], &mut out);
+        let [centroid, _, rolloff, flatness] = out[0];
+        assert!((centroid - 250.0).abs() < 1e-2);
+        // 85% of the 49 bins above DC
+        assert!((rolloff - 420.0).abs() < 1e-2);
+        assert!((flatness - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn silence_has_no_features() {
+        let mut features = SpectralFeatures::new(2, 1000, 100);
+        let mut out = [[1.0; 4]; 2];
+        features.process(&[vec![0.0; 50], vec![0.0; 50]], &mut out);
+        assert_eq!(out, [[0.0; 4]; 2]);
+    }
+}
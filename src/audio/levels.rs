@@ -0,0 +1,204 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+// How long a peak stays put before it starts falling, in seconds
+const PEAK_HOLD: f32 = 1.0;
+// How fast a held peak falls afterwards, in decades per second (20 dB/s)
+const PEAK_FALL: f32 = 1.0;
+// Length of the short-term loudness window, in seconds
+const SHORT_TERM: f32 = 3.0;
+// Loudness reported for silence, instead of -inf
+const MIN_LUFS: f32 = -70.0;
+
+/// Second order IIR filter, transposed direct form II
+#[derive(Clone)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// The two stage K-weighting filter of ITU-R BS.1770, for any sample rate.
+/// A high shelf for the head, then a high pass cutting the rumble.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let fs = sample_rate as f64;
+
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / fs).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+fn lufs(mean_square: f64) -> f32 {
+    if mean_square <= 0.0 {
+        MIN_LUFS
+    } else {
+        (-0.691 + 10.0 * mean_square.log10()).max(MIN_LUFS as f64) as f32
+    }
+}
+
+/// RMS, peak with hold and short-term loudness (LUFS) of every channel, and of all of them together
+pub struct LevelMeter {
+    filters: Vec<[Biquad; 2]>,
+    held_peaks: Vec<(f32, f32)>,
+    // Mean square of the K-weighted samples of every channel, for the last reads
+    history: VecDeque<Vec<f64>>,
+    history_len: usize,
+}
+
+impl LevelMeter {
+    /// `hop_size` is the number of samples per channel of every call to `process`
+    pub fn new(channels: usize, sample_rate: u32, hop_size: usize) -> Self {
+        LevelMeter {
+            filters: vec![k_weighting(sample_rate); channels],
+            held_peaks: vec![(0.0, 0.0); channels + 1],
+            history: VecDeque::new(),
+            history_len: ((SHORT_TERM * sample_rate as f32 / hop_size as f32).ceil() as usize)
+                .max(1),
+        }
+    }
+
+    /// Measures the interleaved `data`, `dt` is its duration in seconds.
+    /// `levels[0]` gets all the channels together, `levels[c + 1]` channel `c`,
+    /// both as [rms, peak, held peak, short-term loudness in LUFS]
+    pub fn process(&mut self, data: &[f32], dt: f32, levels: &mut [[f32; 4]]) {
+        let channels = self.filters.len();
+        let frames = (data.len() / channels).max(1);
+
+        // Reuse the entry that drops out of the window
+        let mut weighted = if self.history.len() == self.history_len {
+            self.history.pop_front().unwrap()
+        } else {
+            vec![0.0f64; channels]
+        };
+        weighted.fill(0.0);
+        for (channel, [shelf, high_pass]) in self.filters.iter_mut().enumerate() {
+            let mut square_sum = 0.0f32;
+            let mut peak = 0.0f32;
+            for &sample in data.iter().skip(channel).step_by(channels) {
+                square_sum += sample * sample;
+                peak = peak.max(sample.abs());
+                let k = high_pass.process(shelf.process(sample as f64));
+                weighted[channel] += k * k;
+            }
+            weighted[channel] /= frames as f64;
+            levels[channel + 1][0] = (square_sum / frames as f32).sqrt();
+            levels[channel + 1][1] = peak;
+        }
+
+        self.history.push_back(weighted);
+
+        let mut total = 0.0;
+        for channel in 0..channels {
+            let mean_square =
+                self.history.iter().map(|w| w[channel]).sum::<f64>() / self.history.len() as f64;
+            levels[channel + 1][3] = lufs(mean_square);
+            // Every channel has a weight of 1, the surround ones are not treated specially
+            total += mean_square;
+        }
+        levels[0][0] =
+            (levels[1..].iter().map(|l| l[0] * l[0]).sum::<f32>() / channels as f32).sqrt();
+        levels[0][1] = levels[1..].iter().fold(0.0f32, |max, l| max.max(l[1]));
+        levels[0][3] = lufs(total);
+
+        for (level, (held, since)) in levels.iter_mut().zip(self.held_peaks.iter_mut()) {
+            if level[1] >= *held {
+                *held = level[1];
+                *since = 0.0;
+            } else {
+                *since += dt;
+                if *since > PEAK_HOLD {
+                    *held = (*held * 10f32.powf(-PEAK_FALL * dt)).max(level[1]);
+                }
+            }
+            level[2] = *held;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f32, sample_rate: u32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|n| (std::f32::consts::TAU * freq * n as f32 / sample_rate as f32).sin())
+            .collect()
+    }
+
+    #[test]
+    fn full_scale_sine() {
+        // BS.1770: a full scale 997 Hz sine in one channel reads -3.01 LUFS
+        let mut meter = LevelMeter::new(1, 48000, 4800);
+        let mut levels = [[0.0; 4]; 2];
+        let samples = sine(997.0, 48000, 48000 * 3);
+        for data in samples.chunks_exact(4800) {
+            meter.process(data, 0.1, &mut levels);
+        }
+        assert!((levels[1][0] - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-3);
+        assert!((levels[1][1] - 1.0).abs() < 1e-3);
+        assert!((levels[1][3] + 3.01).abs() < 0.1, "{}", levels[1][3]);
+        assert_eq!(levels[0], levels[1]);
+    }
+
+    #[test]
+    fn silence_bottoms_out() {
+        let mut meter = LevelMeter::new(2, 48000, 480);
+        let mut levels = [[1.0; 4]; 3];
+        meter.process(&[0.0; 960], 0.01, &mut levels);
+        for level in levels {
+            assert_eq!(level, [0.0, 0.0, 0.0, MIN_LUFS]);
+        }
+    }
+
+    #[test]
+    fn peak_is_held_then_falls() {
+        let mut meter = LevelMeter::new(1, 1000, 100);
+        let mut levels = [[0.0; 4]; 2];
+        let mut loud = [0.0; 100];
+        loud[50] = -1.0;
+        meter.process(&loud, 0.1, &mut levels);
+        assert_eq!(levels[1][2], 1.0);
+
+        for _ in 0..9 {
+            meter.process(&[0.0; 100], 0.1, &mut levels);
+        }
+        assert_eq!(levels[1][1], 0.0);
+        assert_eq!(levels[1][2], 1.0);
+
+        for _ in 0..11 {
+            meter.process(&[0.0; 100], 0.1, &mut levels);
+        }
+        assert!(levels[1][2] > 0.0 && levels[1][2] < 0.2);
+    }
+}
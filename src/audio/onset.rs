@@ -0,0 +1,118 @@
+use std::collections::VecDeque;
+
+/// Spectral flux onset detection on the frequency bands.
+/// A band has an onset when its rise in energy is a lot bigger than its recent average rise.
+pub struct OnsetDetector {
+    sensitivity: f32,
+    history_len: usize,
+    previous: Vec<f32>,
+    band_history: Vec<VecDeque<f32>>,
+    total_history: VecDeque<f32>,
+}
+
+// Without a lower bound, silence with a bit of noise would trigger beats all the time
+const MIN_FLUX: f32 = 1e-3;
+
+impl OnsetDetector {
+    pub fn new(bands: usize, history_len: usize, sensitivity: f32) -> Self {
+        OnsetDetector {
+            sensitivity,
+            history_len: history_len.max(1),
+            previous: vec![0.0; bands],
+            band_history: vec![VecDeque::new(); bands],
+            total_history: VecDeque::new(),
+        }
+    }
+
+    fn is_onset(
+        history: &mut VecDeque<f32>,
+        history_len: usize,
+        flux: f32,
+        sensitivity: f32,
+    ) -> bool {
+        let mean = if history.is_empty() {
+            f32::MAX
+        } else {
+            history.iter().sum::<f32>() / history.len() as f32
+        };
+        if history.len() == history_len {
+            history.pop_front();
+        }
+        history.push_back(flux);
+        flux > MIN_FLUX && flux > mean * sensitivity
+    }
+
+    /// Takes the current band magnitudes, writes the flux and whether there is an onset for every band.
+    /// Returns the onset strength and whether the whole spectrum has an onset (a beat)
+    pub fn process(&mut self, bands: &[f32], flux: &mut [f32], onsets: &mut [bool]) -> (f32, bool) {
+        let mut total = 0.0;
+        for (band, value) in bands.iter().enumerate() {
+            // Only rising energy is interesting
+            let band_flux = (value - self.previous[band]).max(0.0);
+            self.previous[band] = *value;
+            flux[band] = band_flux;
+            onsets[band] = Self::is_onset(
+                &mut self.band_history[band],
+                self.history_len,
+                band_flux,
+                self.sensitivity,
+            );
+            total += band_flux;
+        }
+        let beat = Self::is_onset(
+            &mut self.total_history,
+            self.history_len,
+            total,
+            self.sensitivity,
+        );
+        (total, beat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rising_band_is_an_onset() {
+        let mut detector = OnsetDetector::new(2, 4, 1.5);
+        let mut flux = [0.0; 2];
+        let mut onsets = [false; 2];
+        for _ in 0..5 {
+            let (_, beat) = detector.process(&[0.1, 0.1], &mut flux, &mut onsets);
+            assert!(!beat);
+            assert_eq!(onsets, [false, false]);
+        }
+
+        let (strength, beat) = detector.process(&[1.0, 0.1], &mut flux, &mut onsets);
+        assert!((strength - 0.9).abs() < 1e-6);
+        assert!(beat);
+        assert_eq!(onsets, [true, false]);
+        assert!((flux[0] - 0.9).abs() < 1e-6);
+        assert_eq!(flux[1], 0.0);
+    }
+
+    #[test]
+    fn falling_energy_has_no_flux() {
+        let mut detector = OnsetDetector::new(1, 4, 1.5);
+        let mut flux = [0.0];
+        let mut onsets = [false];
+        detector.process(&[1.0], &mut flux, &mut onsets);
+        let (strength, beat) = detector.process(&[0.2], &mut flux, &mut onsets);
+        assert_eq!(strength, 0.0);
+        assert_eq!(flux, [0.0]);
+        assert!(!beat && !onsets[0]);
+    }
+
+    #[test]
+    fn noise_floor_never_triggers() {
+        let mut detector = OnsetDetector::new(1, 4, 1.5);
+        let mut flux = [0.0];
+        let mut onsets = [false];
+        for i in 0..20 {
+            let value = if i % 2 == 0 { 0.0 } else { MIN_FLUX / 2.0 };
+            let (_, beat) = detector.process(&[value], &mut flux, &mut onsets);
+            assert!(!beat && !onsets[0]);
+        }
+    }
+}
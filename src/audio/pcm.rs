@@ -0,0 +1,260 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use super::{AudioError, AudioSource, Waker};
+
+/// Sample format of the raw pcm data
+#[derive(Clone, Copy)]
+pub enum PcmFormat {
+    F32,
+    S16,
+}
+
+impl PcmFormat {
+    fn size(&self) -> usize {
+        match self {
+            PcmFormat::F32 => 4,
+            PcmFormat::S16 => 2,
+        }
+    }
+}
+
+/// Reads interleaved native endian pcm from stdin or a named pipe, like the fifo output of MPD.
+/// A regular file is played once in real time, the capture loop opens it again after its end.
+pub struct PcmSource {
+    // None means stdin
+    path: Option<PathBuf>,
+    file: File,
+    // Becomes readable once the capture gets stopped, so a read waiting for data can give up
+    wake_receiver: OwnedFd,
+    wake_sender: Arc<File>,
+    // A regular file can be read as fast as the disk goes, so it gets paced like a device
+    is_file: bool,
+    start: Instant,
+    frames_read: u64,
+    format: PcmFormat,
+    channels: usize,
+    sample_rate: u32,
+    bytes: Vec<u8>,
+}
+
+impl PcmSource {
+    /// A path of `-` reads from stdin
+    pub fn open(
+        path: PathBuf,
+        format: PcmFormat,
+        channels: usize,
+        sample_rate: u32,
+    ) -> io::Result<Self> {
+        let path = (path.as_os_str() != "-").then_some(path);
+        let file = match &path {
+            Some(path) => open_nonblocking(path)?,
+            // A copy of the descriptor, stdin itself stays blocking for everyone else
+            None => File::from(io::stdin().as_fd().try_clone_to_owned()?),
+        };
+        let is_file = file.metadata()?.is_file();
+        let (wake_receiver, wake_sender) = pipe()?;
+        Ok(PcmSource {
+            path,
+            file,
+            wake_receiver,
+            wake_sender: Arc::new(wake_sender),
+            is_file,
+            start: Instant::now(),
+            frames_read: 0,
+            format,
+            channels,
+            sample_rate,
+            bytes: vec![],
+        })
+    }
+
+    /// Waits until the file has something to read, or the capture gets stopped
+    fn wait(&self) -> Result<(), AudioError> {
+        let mut fds =
+            [self.file.as_raw_fd(), self.wake_receiver.as_raw_fd()].map(|fd| libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            });
+        // SAFETY: both descriptors stay open for the whole call
+        while unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err.into());
+            }
+        }
+        if fds[1].revents != 0 {
+            return Err(AudioError::Disconnected(
+                "the capture was stopped".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Opening a fifo would otherwise wait for a writer, where nothing can interrupt it
+fn open_nonblocking(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+}
+
+fn pipe() -> io::Result<(OwnedFd, File)> {
+    let mut fds = [0; 2];
+    // SAFETY: pipe2 fills in two new descriptors, which nothing else owns
+    unsafe {
+        if libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((OwnedFd::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])))
+    }
+}
+
+impl AudioSource for PcmSource {
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn read(&mut self, data: &mut [f32]) -> Result<(), AudioError> {
+        self.bytes.resize(data.len() * self.format.size(), 0);
+        let mut filled = 0;
+        while filled < self.bytes.len() {
+            self.wait()?;
+            match (self.file.read(&mut self.bytes[filled..]), &self.path) {
+                (Ok(0), Some(path)) if self.is_file => {
+                    // Reopening it right away would spin at the end of an empty file
+                    return Err(AudioError::Disconnected(format!(
+                        "reached the end of {}",
+                        path.display()
+                    )));
+                }
+                // The writer of a fifo went away (eg. MPD stopped playing),
+                // the one opened again only becomes readable with the next one
+                (Ok(0), Some(path)) => {
+                    self.file = open_nonblocking(path)?;
+                    filled = 0;
+                }
+                (Ok(0), None) => {
+                    return Err(AudioError::Disconnected("stdin was closed".to_string()))
+                }
+                (Ok(read), _) => filled += read,
+                (Err(err), _)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+                    ) => {}
+                (Err(err), _) => return Err(err.into()),
+            }
+        }
+
+        match self.format {
+            PcmFormat::F32 => {
+                for (sample, c) in data.iter_mut().zip(self.bytes.chunks_exact(4)) {
+                    *sample = f32::from_ne_bytes([c[0], c[1], c[2], c[3]]);
+                }
+            }
+            PcmFormat::S16 => {
+                for (sample, c) in data.iter_mut().zip(self.bytes.chunks_exact(2)) {
+                    *sample = i16::from_ne_bytes([c[0], c[1]]) as f32 / 32768.0;
+                }
+            }
+        }
+
+        if self.is_file {
+            // Start the clock on the first read, not when the file was opened
+            if self.frames_read == 0 {
+                self.start = Instant::now();
+            }
+            self.frames_read += (data.len() / self.channels) as u64;
+            let due = Duration::from_secs_f64(self.frames_read as f64 / self.sample_rate as f64);
+            if let Some(wait) = due.checked_sub(self.start.elapsed()) {
+                sleep(wait);
+            }
+        }
+        Ok(())
+    }
+
+    fn waker(&self) -> Option<Waker> {
+        let wake_sender = self.wake_sender.clone();
+        // The byte stays in the pipe, so every later read fails as well
+        Some(Box::new(move || {
+            let _ = (&*wake_sender).write(&[0]);
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_file(name: &str, samples: &[i16]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("wgpu-paper-{}-{}.pcm", name, std::process::id()));
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_ne_bytes()).collect();
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn regular_file_is_paced_and_ends() {
+        let path = write_file("paced", &[16384; 100]);
+        let mut source = PcmSource::open(path.clone(), PcmFormat::S16, 1, 1000).unwrap();
+        let start = Instant::now();
+        let mut data = [0.0; 25];
+        for _ in 0..4 {
+            source.read(&mut data).unwrap();
+            assert_eq!(data, [0.5; 25]);
+        }
+        // 100 samples at 1 kHz
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(matches!(
+            source.read(&mut data),
+            Err(AudioError::Disconnected(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn empty_file_fails_right_away() {
+        let path = write_file("empty", &[]);
+        let mut source = PcmSource::open(path.clone(), PcmFormat::S16, 2, 44100).unwrap();
+        assert!(source.read(&mut [0.0; 8]).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn waker_stops_a_waiting_fifo() {
+        let path = std::env::temp_dir().join(format!("wgpu-paper-fifo-{}.pcm", std::process::id()));
+        let c_path =
+            std::ffi::CString::new(std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()))
+                .unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+        // Nothing ever writes to it
+        let mut source = PcmSource::open(path.clone(), PcmFormat::S16, 2, 44100).unwrap();
+        let waker = source.waker().unwrap();
+        let wake = std::thread::spawn(move || {
+            sleep(Duration::from_millis(50));
+            waker();
+        });
+        assert!(matches!(
+            source.read(&mut [0.0; 8]),
+            Err(AudioError::Disconnected(_))
+        ));
+        wake.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
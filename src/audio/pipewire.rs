@@ -0,0 +1,302 @@
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    rc::Rc,
+    sync::mpsc::{self, Receiver, Sender},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use pipewire as pw;
+use pw::{properties::properties, spa};
+
+use super::{AudioError, AudioSource, Waker};
+
+enum Message {
+    Samples(Vec<f32>),
+    // The stream lost its node, or couldn't connect to it
+    Disconnected(String),
+}
+
+// Tells the PipeWire thread to quit its main loop
+struct Terminate;
+
+// How long PipeWire gets to list its nodes, before the target counts as missing
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Records directly from PipeWire, without going through the pulse compatibility layer.
+/// The stream lives on its own thread, since PipeWire objects can't leave the thread of their main loop.
+pub struct PipewireSource {
+    channels: usize,
+    sample_rate: u32,
+    receiver: Receiver<Message>,
+    pending: VecDeque<f32>,
+    terminate: pw::channel::Sender<Terminate>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PipewireSource {
+    /// `target` is the name or serial of the node to record, a sink, an application stream or a microphone.
+    /// With `capture_sink` the monitor of the target sink (or of the default one) is recorded.
+    pub fn new(
+        target: Option<String>,
+        capture_sink: bool,
+        channels: usize,
+        sample_rate: u32,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let (terminate, terminate_receiver) = pw::channel::channel();
+        let thread = thread::spawn(move || {
+            let error_sender = sender.clone();
+            if let Err(err) = run_stream(
+                target,
+                capture_sink,
+                channels,
+                sample_rate,
+                sender,
+                terminate_receiver,
+            ) {
+                let _ = error_sender.send(Message::Disconnected(err.to_string()));
+            }
+        });
+
+        PipewireSource {
+            channels,
+            sample_rate,
+            receiver,
+            pending: VecDeque::new(),
+            terminate,
+            thread: Some(thread),
+        }
+    }
+}
+
+fn run_stream(
+    target: Option<String>,
+    capture_sink: bool,
+    channels: usize,
+    sample_rate: u32,
+    sender: Sender<Message>,
+    terminate: pw::channel::Receiver<Terminate>,
+) -> Result<(), pw::Error> {
+    let mainloop = pw::main_loop::MainLoop::new(None)?;
+    let terminated = Rc::new(Cell::new(false));
+    let _terminate = terminate.attach(mainloop.loop_(), {
+        let mainloop = mainloop.clone();
+        let terminated = terminated.clone();
+        move |_| {
+            terminated.set(true);
+            mainloop.quit();
+        }
+    });
+    let context = pw::context::Context::new(&mainloop)?;
+    let core = context.connect(None)?;
+
+    // A stream aimed at a node that doesn't exist would wait for it forever, without an error
+    if let Some(target) = &target {
+        let reason = match node_exists(&mainloop, &core, target)? {
+            Some(true) => None,
+            Some(false) => Some(format!("PipeWire node {} doesn't exist", target)),
+            None if terminated.get() => return Ok(()),
+            None => Some(format!(
+                "PipeWire didn't list its nodes within {} s",
+                RESOLVE_TIMEOUT.as_secs()
+            )),
+        };
+        if let Some(reason) = reason {
+            let _ = sender.send(Message::Disconnected(reason));
+            return Ok(());
+        }
+    }
+
+    let mut props = properties! {
+        *pw::keys::MEDIA_TYPE => "Audio",
+        *pw::keys::MEDIA_CATEGORY => "Capture",
+        *pw::keys::MEDIA_ROLE => "Music",
+    };
+    if let Some(target) = &target {
+        props.insert(*pw::keys::TARGET_OBJECT, target.as_str());
+    }
+    if capture_sink {
+        props.insert(*pw::keys::STREAM_CAPTURE_SINK, "true");
+    }
+
+    let stream = pw::stream::Stream::new(&core, "wgpu-paper", props)?;
+
+    let node = target.unwrap_or_else(|| "default".to_string());
+    let state_sender = sender.clone();
+    let state_mainloop = mainloop.clone();
+    let _listener = stream
+        .add_local_listener_with_user_data(sender)
+        .state_changed(move |_, _, old, new| {
+            let reason = match new {
+                pw::stream::StreamState::Error(err) => err,
+                // Without reconnecting, the stream only becomes unconnected when its node goes away.
+                // The capture loop then opens a new stream, once the node is back
+                pw::stream::StreamState::Unconnected
+                    if !matches!(old, pw::stream::StreamState::Unconnected) =>
+                {
+                    "the node disappeared".to_string()
+                }
+                _ => return,
+            };
+            let _ = state_sender.send(Message::Disconnected(format!(
+                "PipeWire node {}: {}",
+                node, reason
+            )));
+            state_mainloop.quit();
+        })
+        .process(|stream, sender| {
+            let Some(mut buffer) = stream.dequeue_buffer() else {
+                return;
+            };
+            let datas = buffer.datas_mut();
+            if datas.is_empty() {
+                return;
+            }
+            let data = &mut datas[0];
+            let offset = data.chunk().offset() as usize;
+            let size = data.chunk().size() as usize;
+            if let Some(bytes) = data.data() {
+                let end = (offset + size).min(bytes.len());
+                let samples = bytes[offset.min(end)..end]
+                    .chunks_exact(4)
+                    .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                    .collect();
+                let _ = sender.send(Message::Samples(samples));
+            }
+        })
+        .register()?;
+
+    // Ask for the same format the other backends produce, PipeWire converts if needed
+    let mut audio_info = spa::param::audio::AudioInfoRaw::new();
+    audio_info.set_format(spa::param::audio::AudioFormat::F32LE);
+    audio_info.set_rate(sample_rate);
+    audio_info.set_channels(channels as u32);
+    let obj = spa::pod::Object {
+        type_: spa::utils::SpaTypes::ObjectParamFormat.as_raw(),
+        id: spa::param::ParamType::EnumFormat.as_raw(),
+        properties: audio_info.into(),
+    };
+    let values: Vec<u8> = spa::pod::serialize::PodSerializer::serialize(
+        std::io::Cursor::new(Vec::new()),
+        &spa::pod::Value::Object(obj),
+    )
+    .expect("Unable to serialize the audio format")
+    .0
+    .into_inner();
+    let mut params = [spa::pod::Pod::from_bytes(&values).unwrap()];
+
+    stream.connect(
+        spa::utils::Direction::Input,
+        None,
+        pw::stream::StreamFlags::AUTOCONNECT
+            | pw::stream::StreamFlags::MAP_BUFFERS
+            // Report when the node goes away, instead of silently moving to another one
+            | pw::stream::StreamFlags::DONT_RECONNECT,
+        &mut params,
+    )?;
+
+    mainloop.run();
+    Ok(())
+}
+
+/// Whether the registry lists a node with `target` as its name or serial.
+/// None when the main loop was quit, or the registry didn't answer in time.
+fn node_exists(
+    mainloop: &pw::main_loop::MainLoop,
+    core: &pw::core::Core,
+    target: &str,
+) -> Result<Option<bool>, pw::Error> {
+    let registry = core.get_registry()?;
+    let found = Rc::new(Cell::new(false));
+    let answer = Rc::new(Cell::new(None));
+    let _registry_listener = registry
+        .add_listener_local()
+        .global({
+            let found = found.clone();
+            let target = target.to_string();
+            move |global| {
+                let Some(props) = &global.props else {
+                    return;
+                };
+                if global.type_ == pw::types::ObjectType::Node
+                    && (props.get(*pw::keys::NODE_NAME) == Some(target.as_str())
+                        || props.get(*pw::keys::OBJECT_SERIAL) == Some(target.as_str()))
+                {
+                    found.set(true);
+                }
+            }
+        })
+        .register();
+
+    // The registry announces every existing object before it answers the sync
+    let pending = core.sync(0)?;
+    let _core_listener = core
+        .add_listener_local()
+        .done({
+            let answer = answer.clone();
+            let mainloop = mainloop.clone();
+            move |id, seq| {
+                if id == pw::core::PW_ID_CORE && seq == pending {
+                    answer.set(Some(found.get()));
+                    mainloop.quit();
+                }
+            }
+        })
+        .register();
+    let timeout = mainloop.loop_().add_timer({
+        let mainloop = mainloop.clone();
+        move |_| mainloop.quit()
+    });
+    let _ = timeout.update_timer(Some(RESOLVE_TIMEOUT), None);
+
+    mainloop.run();
+    Ok(answer.get())
+}
+
+impl AudioSource for PipewireSource {
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn read(&mut self, data: &mut [f32]) -> Result<(), AudioError> {
+        while self.pending.len() < data.len() {
+            match self.receiver.recv() {
+                Ok(Message::Samples(samples)) => self.pending.extend(samples),
+                Ok(Message::Disconnected(reason)) => return Err(AudioError::Disconnected(reason)),
+                Err(_) => {
+                    return Err(AudioError::Disconnected(
+                        "the PipeWire thread exited".to_string(),
+                    ))
+                }
+            }
+        }
+        for sample in data.iter_mut() {
+            *sample = self.pending.pop_front().unwrap();
+        }
+        Ok(())
+    }
+
+    // The thread exits with its main loop, which ends a recv waiting for samples
+    fn waker(&self) -> Option<Waker> {
+        let terminate = self.terminate.clone();
+        Some(Box::new(move || {
+            let _ = terminate.send(Terminate);
+        }))
+    }
+}
+
+impl Drop for PipewireSource {
+    // Otherwise the stream would keep recording on its thread, after a reopen or once the capture stopped
+    fn drop(&mut self) {
+        let _ = self.terminate.send(Terminate);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,180 @@
+// Range searched for the fundamental, in Hz
+const MIN_PITCH: f32 = 50.0;
+const MAX_PITCH: f32 = 2000.0;
+// Highest frequency that counts towards the chroma, the rest is mostly overtones and noise
+const MAX_CHROMA_FREQ: f32 = 5000.0;
+// Number of harmonics multiplied together by the harmonic product spectrum
+const HARMONICS: usize = 5;
+// Below this magnitude the spectrum is considered silent, and there is no pitch
+const MIN_MAGNITUDE: f32 = 1e-4;
+
+/// Pitch class energy (C, C#, D, ... B) and the dominant fundamental frequency of a spectrum
+pub struct PitchDetector {
+    bin_width: f32,
+    // Pitch class of every fft bin, None for the bins too wide or too high to belong to a single note
+    bin_classes: Vec<Option<usize>>,
+    log_spectrum: Vec<f32>,
+}
+
+/// Fractional MIDI note number of a frequency, A4 (440 Hz) is 69
+pub fn midi_note(freq: f32) -> f32 {
+    69.0 + 12.0 * (freq / 440.0).log2()
+}
+
+impl PitchDetector {
+    pub fn new(sample_rate: u32, fft_size: usize) -> Self {
+        let bin_width = sample_rate as f32 / fft_size as f32;
+        // A bin has to be narrower than a semitone, otherwise it would smear neighbouring notes together
+        let min_freq = bin_width / (2f32.powf(1.0 / 12.0) - 1.0);
+        let bin_classes = (0..fft_size / 2)
+            .map(|bin| {
+                let freq = bin as f32 * bin_width;
+                (freq >= min_freq && freq <= MAX_CHROMA_FREQ)
+                    .then(|| midi_note(freq).round().rem_euclid(12.0) as usize)
+            })
+            .collect();
+        PitchDetector {
+            bin_width,
+            bin_classes,
+            log_spectrum: vec![0.0; fft_size / 2],
+        }
+    }
+
+    /// Fills `chroma` with the energy of every pitch class, scaled so the strongest one is 1.
+    /// Returns the fundamental frequency in Hz and how much it stands out (0..1), or zeros on silence.
+    pub fn process(&mut self, spectrum: &[f32], chroma: &mut [f32; 12]) -> (f32, f32) {
+        chroma.fill(0.0);
+        for (magnitude, class) in spectrum.iter().zip(&self.bin_classes) {
+            if let Some(class) = class {
+                chroma[*class] += magnitude * magnitude;
+            }
+        }
+        let max = chroma.iter().fold(0.0f32, |max, c| max.max(*c));
+        if max > MIN_MAGNITUDE * MIN_MAGNITUDE {
+            for c in chroma.iter_mut() {
+                *c /= max;
+            }
+        }
+
+        let peak = spectrum.iter().fold(0.0f32, |max, m| max.max(*m));
+        if peak < MIN_MAGNITUDE {
+            return (0.0, 0.0);
+        }
+
+        // Harmonic product spectrum, summed as logarithms so it doesn't underflow.
+        // Only the fundamental has energy at all of its multiples.
+        for (log, magnitude) in self.log_spectrum.iter_mut().zip(spectrum) {
+            *log = (magnitude / peak).max(MIN_MAGNITUDE).ln();
+        }
+        let score = |bin: usize| -> f32 {
+            (1..=HARMONICS)
+                .map(|harmonic| self.log_spectrum[bin * harmonic])
+                .sum()
+        };
+        let min_bin = ((MIN_PITCH / self.bin_width).ceil() as usize).max(1);
+        let max_bin = ((MAX_PITCH / self.bin_width) as usize).min((spectrum.len() - 1) / HARMONICS);
+        let Some(bin) = (min_bin..max_bin).max_by(|a, b| score(*a).total_cmp(&score(*b))) else {
+            return (0.0, 0.0);
+        };
+
+        // Refine on the strongest harmonic, which is also there when the fundamental is missing.
+        // Parabolic interpolation between its neighbouring bins gives a resolution finer than a bin
+        let harmonic = (1..=HARMONICS)
+            .max_by(|a, b| spectrum[bin * a].total_cmp(&spectrum[bin * b]))
+            .unwrap();
+        let center_bin = bin * harmonic;
+        let (left, center, right) = (
+            spectrum[center_bin - 1],
+            spectrum[center_bin],
+            spectrum[center_bin + 1],
+        );
+        let denominator = left - 2.0 * center + right;
+        let offset = if denominator.abs() > f32::EPSILON {
+            (0.5 * (left - right) / denominator).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        };
+
+        // Share of the energy that belongs to the harmonics
+        let total: f32 = spectrum.iter().map(|m| m * m).sum();
+        let harmonics: f32 = (1..=HARMONICS)
+            .flat_map(|harmonic| bin * harmonic - 1..=bin * harmonic + 1)
+            .map(|bin| spectrum[bin] * spectrum[bin])
+            .sum();
+        let clarity = (harmonics / total).min(1.0);
+        let freq = (center_bin as f32 + offset) * self.bin_width / harmonic as f32;
+        (freq, clarity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::WindowFunction;
+    use rustfft::{num_complex::Complex, FftPlanner};
+
+    const SAMPLE_RATE: u32 = 44100;
+    const FFT_SIZE: usize = 8192;
+
+    // Magnitude spectrum of a tone made of the given harmonics of `freq`, falling off as 1/k
+    fn spectrum(freq: f32, harmonics: &[usize]) -> Vec<f32> {
+        let window = WindowFunction::Hann.coefficients(FFT_SIZE);
+        let mut buffer: Vec<_> = (0..FFT_SIZE)
+            .map(|n| {
+                let t = n as f32 / SAMPLE_RATE as f32;
+                let sample: f32 = harmonics
+                    .iter()
+                    .map(|k| (std::f32::consts::TAU * freq * *k as f32 * t).sin() / *k as f32)
+                    .sum();
+                Complex {
+                    re: sample * window[n],
+                    im: 0.0,
+                }
+            })
+            .collect();
+        FftPlanner::new()
+            .plan_fft_forward(FFT_SIZE)
+            .process(&mut buffer);
+        buffer[..FFT_SIZE / 2].iter().map(|c| c.norm()).collect()
+    }
+
+    #[test]
+    fn midi_notes() {
+        assert!((midi_note(440.0) - 69.0).abs() < 1e-5);
+        assert!((midi_note(261.6256) - 60.0).abs() < 1e-3);
+        assert!((midi_note(880.0) - 81.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn finds_the_fundamental() {
+        let mut detector = PitchDetector::new(SAMPLE_RATE, FFT_SIZE);
+        let mut chroma = [0.0; 12];
+        let (freq, clarity) = detector.process(&spectrum(220.0, &[1, 2, 3, 4, 5]), &mut chroma);
+        assert!((freq - 220.0).abs() < 1.0, "{}", freq);
+        assert!(clarity > 0.5);
+        // A
+        assert_eq!(chroma[9], 1.0);
+        // E, the fifth brought in by the third harmonic, is the only other strong class
+        assert!(chroma[4] > 0.05);
+        assert!(chroma[0] < 0.01 && chroma[6] < 0.01);
+    }
+
+    #[test]
+    fn finds_a_missing_fundamental() {
+        let mut detector = PitchDetector::new(SAMPLE_RATE, FFT_SIZE);
+        let mut chroma = [0.0; 12];
+        let (freq, _) = detector.process(&spectrum(220.0, &[2, 3, 4, 5]), &mut chroma);
+        assert!((freq - 220.0).abs() < 1.0, "{}", freq);
+    }
+
+    #[test]
+    fn silence_has_no_pitch() {
+        let mut detector = PitchDetector::new(SAMPLE_RATE, FFT_SIZE);
+        let mut chroma = [1.0; 12];
+        assert_eq!(
+            detector.process(&vec![0.0; FFT_SIZE / 2], &mut chroma),
+            (0.0, 0.0)
+        );
+        assert_eq!(chroma, [0.0; 12]);
+    }
+}
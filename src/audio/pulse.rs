@@ -0,0 +1,167 @@
+use std::{cell::RefCell, rc::Rc};
+
+use psimple::Simple;
+use pulse::callbacks::ListResult;
+use pulse::context::{Context, FlagSet, State};
+use pulse::def::BufferAttr;
+use pulse::error::{Code, PAErr};
+use pulse::mainloop::standard::{IterateResult, Mainloop};
+use pulse::operation::{self, Operation};
+use pulse::sample::{Format, Spec};
+use pulse::stream::Direction;
+
+use super::{AudioError, AudioSource};
+
+/// Records from a PulseAudio source, or the default one
+pub struct PulseSource {
+    simple: Simple,
+    channels: usize,
+    sample_rate: u32,
+    bytes: Vec<u8>,
+}
+
+impl PulseSource {
+    pub fn new(
+        device_name: Option<&str>,
+        channels: usize,
+        sample_rate: u32,
+        fragment_size: usize,
+    ) -> Result<Self, AudioError> {
+        let spec = Spec {
+            format: Format::FLOAT32NE,
+            channels: u8::try_from(channels).map_err(|_| PAErr::from(Code::Invalid))?,
+            rate: sample_rate,
+        };
+        // PulseAudio takes up to 32 channels, at a sample rate it supports
+        if !spec.is_valid() {
+            return Err(PAErr::from(Code::Invalid).into());
+        }
+
+        let attr = BufferAttr {
+            maxlength: u32::MAX,
+            fragsize: (fragment_size * std::mem::size_of::<f32>() * channels) as u32,
+            ..Default::default()
+        };
+
+        let simple = Simple::new(
+            None,              // Use the default server
+            "wgpu-paper",      // Our application’s name
+            Direction::Record, // We want a playback stream
+            device_name,       // Use the default device
+            "Music",           // Description of our stream
+            &spec,             // Our sample format
+            None,              // Use default channel map
+            Some(&attr),       // Use default buffering attributes
+        )?;
+
+        Ok(PulseSource {
+            simple,
+            channels,
+            sample_rate,
+            bytes: vec![],
+        })
+    }
+}
+
+impl AudioSource for PulseSource {
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn read(&mut self, data: &mut [f32]) -> Result<(), AudioError> {
+        self.bytes.resize(std::mem::size_of_val(data), 0);
+        self.simple.read(&mut self.bytes)?;
+
+        // Convert the byte array into f32
+        for (sample, c) in data.iter_mut().zip(self.bytes.chunks(4)) {
+            *sample = f32::from_ne_bytes([c[0], c[1], c[2], c[3]]);
+        }
+        Ok(())
+    }
+}
+
+/// A source, as shown by `wgpu-paper audio list`
+pub struct PulseSourceInfo {
+    pub name: String,
+    pub description: String,
+    // Monitors record what a sink is playing
+    pub is_monitor: bool,
+}
+
+// Connects to the default server, and waits until the context is ready
+fn connect() -> Result<(Mainloop, Context), PAErr> {
+    let mut mainloop = Mainloop::new().ok_or(PAErr::from(Code::Internal))?;
+    let mut context = Context::new(&mainloop, "wgpu-paper").ok_or(PAErr::from(Code::Internal))?;
+    context.connect(None, FlagSet::NOFLAGS, None)?;
+    loop {
+        if let IterateResult::Err(err) = mainloop.iterate(true) {
+            return Err(err);
+        }
+        match context.get_state() {
+            State::Ready => return Ok((mainloop, context)),
+            State::Failed | State::Terminated => return Err(context.errno()),
+            _ => {}
+        }
+    }
+}
+
+fn wait<T: ?Sized>(mainloop: &mut Mainloop, operation: &Operation<T>) -> Result<(), PAErr> {
+    while operation.get_state() == operation::State::Running {
+        if let IterateResult::Err(err) = mainloop.iterate(true) {
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+pub fn list_sources() -> Result<Vec<PulseSourceInfo>, PAErr> {
+    let (mut mainloop, context) = connect()?;
+    let sources = Rc::new(RefCell::new(vec![]));
+    let list = sources.clone();
+    let operation = context.introspect().get_source_info_list(move |result| {
+        if let ListResult::Item(info) = result {
+            list.borrow_mut().push(PulseSourceInfo {
+                name: info.name.as_deref().unwrap_or_default().to_string(),
+                description: info.description.as_deref().unwrap_or_default().to_string(),
+                is_monitor: info.monitor_of_sink.is_some(),
+            });
+        }
+    });
+    wait(&mut mainloop, &operation)?;
+    let sources = sources.take();
+    Ok(sources)
+}
+
+/// Name of the monitor of the default sink, what is currently playing
+pub fn default_monitor() -> Result<Option<String>, PAErr> {
+    let (mut mainloop, context) = connect()?;
+    let introspector = context.introspect();
+
+    let default_sink = Rc::new(RefCell::new(None));
+    let sink = default_sink.clone();
+    let operation = introspector.get_server_info(move |info| {
+        *sink.borrow_mut() = info.default_sink_name.as_ref().map(|name| name.to_string());
+    });
+    wait(&mut mainloop, &operation)?;
+    let Some(default_sink) = default_sink.take() else {
+        return Ok(None);
+    };
+
+    let monitor = Rc::new(RefCell::new(None));
+    let source = monitor.clone();
+    let operation = introspector.get_sink_info_by_name(&default_sink, move |result| {
+        if let ListResult::Item(info) = result {
+            *source.borrow_mut() = info
+                .monitor_source_name
+                .as_ref()
+                .map(|name| name.to_string());
+        }
+    });
+    wait(&mut mainloop, &operation)?;
+    let monitor = monitor.take();
+    Ok(monitor)
+}
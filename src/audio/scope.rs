@@ -0,0 +1,173 @@
+/// How the oscilloscope window is placed in the analysed window
+#[derive(Clone, Copy)]
+pub enum ScopeTrigger {
+    // Always the newest samples
+    None,
+    // Start on a rising zero crossing
+    Rising,
+    // Start where the signal looks most like what was shown last time
+    Correlation,
+}
+
+// A rising crossing only counts after the signal went this far below zero, relative to its peak.
+// Without it, noise around zero triggers all over the place.
+// Also how much deeper than another one a dip has to be to win.
+const HYSTERESIS: f32 = 0.1;
+// How far back from the newest samples the trigger may look, relative to the scope length
+const SEARCH: f32 = 0.5;
+
+/// Picks a stable window of the waveform, so a periodic signal stands still on screen
+pub struct Scope {
+    trigger: ScopeTrigger,
+    samples: usize,
+    // Mix of all the channels, the trigger looks at this
+    mono: Vec<f32>,
+    // The mono window shown last time, for the correlation trigger
+    previous: Vec<f32>,
+}
+
+impl Scope {
+    pub fn new(trigger: ScopeTrigger, samples: usize) -> Self {
+        Scope {
+            trigger,
+            samples,
+            mono: vec![],
+            previous: vec![0.0; samples],
+        }
+    }
+
+    /// Copies the triggered window of every channel into `scope`, one channel after the other
+    pub fn process(&mut self, waveforms: &[Vec<f32>], scope: &mut [f32]) {
+        let Some(length) = waveforms.first().map(|w| w.len()) else {
+            return;
+        };
+        let samples = self.samples.min(length);
+        let latest = length - samples;
+        let earliest = latest.saturating_sub((samples as f32 * SEARCH) as usize);
+
+        self.mono.clear();
+        self.mono.resize(length, 0.0);
+        for wave in waveforms {
+            for (mono, sample) in self.mono.iter_mut().zip(wave) {
+                *mono += sample / waveforms.len() as f32;
+            }
+        }
+
+        let start = match self.trigger {
+            ScopeTrigger::None => latest,
+            ScopeTrigger::Rising => self.rising(earliest, latest).unwrap_or(latest),
+            ScopeTrigger::Correlation => self.correlation(earliest, latest, samples),
+        };
+        self.previous[..samples].copy_from_slice(&self.mono[start..start + samples]);
+
+        for (wave, out) in waveforms.iter().zip(scope.chunks_exact_mut(self.samples)) {
+            out[..samples].copy_from_slice(&wave[start..start + samples]);
+        }
+    }
+
+    // The rising zero crossing that starts a window in earliest..=latest, after the deepest dip.
+    // Waveforms can cross zero upwards several times per period, the deepest dip is the same every period
+    fn rising(&self, earliest: usize, latest: usize) -> Option<usize> {
+        let peak = self.mono.iter().fold(0.0f32, |max, v| max.max(v.abs()));
+        if peak <= f32::EPSILON {
+            return None;
+        }
+        let mut best: Option<(usize, f32)> = None;
+        for i in (earliest.max(1)..=latest).rev() {
+            if !(self.mono[i - 1] <= 0.0 && self.mono[i] > 0.0) {
+                continue;
+            }
+            let depth = -self.mono[..i]
+                .iter()
+                .rev()
+                .take_while(|v| **v <= 0.0)
+                .fold(0.0f32, |min, v| min.min(*v));
+            if depth < HYSTERESIS * peak {
+                continue;
+            }
+            // Older crossings have to be clearly deeper, otherwise the newest one wins
+            match best {
+                Some((_, best_depth)) if depth <= best_depth * (1.0 + HYSTERESIS) => {}
+                _ => best = Some((i, depth)),
+            }
+        }
+        best.map(|(i, _)| i)
+    }
+
+    fn correlation(&self, earliest: usize, latest: usize, samples: usize) -> usize {
+        let previous = &self.previous[..samples];
+        (earliest..=latest)
+            .map(|start| {
+                let window = &self.mono[start..start + samples];
+                let score: f32 = window.iter().zip(previous).map(|(a, b)| a * b).sum();
+                (start, score)
+            })
+            // Ties go to the newest window
+            .fold((latest, f32::MIN), |best, candidate| {
+                if candidate.1 >= best.1 {
+                    candidate
+                } else {
+                    best
+                }
+            })
+            .0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A sine with a period of 100 samples
+    fn sine(length: usize, phase: usize) -> Vec<f32> {
+        (0..length)
+            .map(|n| (std::f32::consts::TAU * (n + phase) as f32 / 100.0).sin())
+            .collect()
+    }
+
+    #[test]
+    fn untriggered_shows_the_newest_samples() {
+        let wave: Vec<f32> = (0..1000).map(|n| n as f32).collect();
+        let mut scope = Scope::new(ScopeTrigger::None, 200);
+        let mut out = vec![0.0; 200];
+        scope.process(std::slice::from_ref(&wave), &mut out);
+        assert_eq!(out, wave[800..]);
+    }
+
+    #[test]
+    fn rising_starts_on_a_zero_crossing() {
+        let mut scope = Scope::new(ScopeTrigger::Rising, 200);
+        let mut out = vec![0.0; 400];
+        for phase in [0, 13, 58] {
+            let wave = sine(1000, phase);
+            scope.process(&[wave.clone(), wave], &mut out);
+            // Every window starts just after the signal crossed zero upwards
+            assert!(out[0] >= 0.0 && out[0] < 0.07, "{}", out[0]);
+            assert!(out[1] > out[0]);
+            assert_eq!(out[..200], out[200..]);
+        }
+    }
+
+    #[test]
+    fn correlation_keeps_the_signal_still() {
+        let mut scope = Scope::new(ScopeTrigger::Correlation, 200);
+        let mut out = vec![0.0; 200];
+        scope.process(&[sine(1000, 0)], &mut out);
+        let first = out.clone();
+        for phase in [21, 47, 90] {
+            scope.process(&[sine(1000, phase)], &mut out);
+            for (a, b) in out.iter().zip(&first) {
+                assert!((a - b).abs() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn scope_longer_than_the_window() {
+        let mut scope = Scope::new(ScopeTrigger::Rising, 200);
+        let mut out = vec![5.0; 200];
+        scope.process(&[vec![1.0; 50]], &mut out);
+        assert_eq!(out[..50], [1.0; 50]);
+        assert_eq!(out[50..], [5.0; 150]);
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+
+/// Exponential attack/release smoothing, attack is used when a value rises, release when it falls
+pub struct Smoother {
+    // Time constants, in seconds
+    attack: f32,
+    release: f32,
+    state: Vec<f32>,
+}
+
+fn coefficient(time_constant: f32, dt: f32) -> f32 {
+    if time_constant <= 0.0 {
+        1.0
+    } else {
+        1.0 - (-dt / time_constant).exp()
+    }
+}
+
+impl Smoother {
+    pub fn new(size: usize, attack: f32, release: f32) -> Self {
+        Smoother {
+            attack,
+            release,
+            state: vec![0.0; size],
+        }
+    }
+
+    /// Smooths `values` in place, `dt` is the time since the last call in seconds
+    pub fn process(&mut self, values: &mut [f32], dt: f32) {
+        let attack = coefficient(self.attack, dt);
+        let release = coefficient(self.release, dt);
+        for (state, value) in self.state.iter_mut().zip(values.iter_mut()) {
+            let k = if *value > *state { attack } else { release };
+            *state += k * (*value - *state);
+            *value = *state;
+        }
+    }
+}
+
+// Below this level everything is considered silence, and doesn't get amplified
+const MIN_LEVEL: f32 = 1e-4;
+
+/// Automatic gain control, scales the values by the biggest magnitude seen in the last `window` seconds
+pub struct AutoGain {
+    window: f32,
+    // (peak, duration) of the last frames
+    peaks: VecDeque<(f32, f32)>,
+}
+
+impl AutoGain {
+    pub fn new(window: f32) -> Self {
+        AutoGain {
+            window,
+            peaks: VecDeque::new(),
+        }
+    }
+
+    /// Normalizes all the buffers with the same gain, `dt` is the time since the last call in seconds
+    pub fn process(&mut self, buffers: &mut [Vec<f32>], dt: f32) {
+        let peak = buffers
+            .iter()
+            .flatten()
+            .fold(0.0f32, |max, v| max.max(v.abs()));
+        self.peaks.push_back((peak, dt));
+        let mut duration: f32 = self.peaks.iter().map(|(_, dt)| dt).sum();
+        while duration > self.window && self.peaks.len() > 1 {
+            duration -= self.peaks.pop_front().unwrap().1;
+        }
+
+        let max = self.peaks.iter().fold(MIN_LEVEL, |max, (p, _)| max.max(*p));
+        for value in buffers.iter_mut().flatten() {
+            *value /= max;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_time_constants_pass_through() {
+        let mut smoother = Smoother::new(2, 0.0, 0.0);
+        let mut values = [1.0, 0.5];
+        smoother.process(&mut values, 0.1);
+        assert_eq!(values, [1.0, 0.5]);
+        let mut values = [0.0, 2.0];
+        smoother.process(&mut values, 0.1);
+        assert_eq!(values, [0.0, 2.0]);
+    }
+
+    #[test]
+    fn release_decays_exponentially() {
+        let mut smoother = Smoother::new(1, 0.0, 1.0);
+        let mut values = [1.0];
+        smoother.process(&mut values, 1.0);
+        assert_eq!(values, [1.0]);
+        // One time constant later, 1/e is left
+        let mut values = [0.0];
+        smoother.process(&mut values, 1.0);
+        assert!((values[0] - (-1.0f32).exp()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn gain_follows_the_peak_of_the_window() {
+        let mut gain = AutoGain::new(2.5);
+        let mut buffers = vec![vec![0.5, -0.25]];
+        gain.process(&mut buffers, 1.0);
+        assert_eq!(buffers, vec![vec![1.0, -0.5]]);
+
+        let mut buffers = vec![vec![0.1]];
+        gain.process(&mut buffers, 1.0);
+        assert!((buffers[0][0] - 0.2).abs() < 1e-6);
+
+        // The loud frame left the window
+        let mut buffers = vec![vec![0.1]];
+        gain.process(&mut buffers, 1.0);
+        assert!((buffers[0][0] - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn silence_is_not_amplified() {
+        let mut gain = AutoGain::new(1.0);
+        let mut buffers = vec![vec![1e-6]];
+        gain.process(&mut buffers, 0.1);
+        assert!((buffers[0][0] - 0.01).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,126 @@
+// Number of L/R pairs given to the shader for the vectorscope
+const VECTORSCOPE_POINTS: usize = 512;
+
+/// Length of the stereo buffer for windows of `samples` samples, mid/side then the vectorscope points
+pub fn stereo_buffer_len(samples: usize) -> usize {
+    samples + samples.min(VECTORSCOPE_POINTS)
+}
+
+/// Mid/side signals, phase correlation and vectorscope points of the first two channels.
+/// A single channel is treated as both left and right.
+///
+/// `stereo` gets (mid, side) for every sample, followed by the decimated (left, right) points.
+/// Returns [phase correlation (-1..1), width, number of points, padding].
+/// The width is the share of the side energy: 0 for mono, 0.5 for unrelated channels, 1 when out of phase.
+pub fn process(waveforms: &[Vec<f32>], stereo: &mut [[f32; 2]]) -> [f32; 4] {
+    let (Some(left), Some(right)) = (waveforms.first(), waveforms.get(1).or(waveforms.first()))
+    else {
+        return [0.0; 4];
+    };
+    let samples = left.len();
+    if samples == 0 {
+        return [0.0; 4];
+    }
+    let (mid_side, points) = stereo.split_at_mut(samples);
+
+    let mut left_right = 0.0f32;
+    let mut left_power = 0.0f32;
+    let mut right_power = 0.0f32;
+    let mut mid_power = 0.0f32;
+    let mut side_power = 0.0f32;
+    for ((l, r), out) in left.iter().zip(right).zip(mid_side.iter_mut()) {
+        let (mid, side) = ((l + r) / 2.0, (l - r) / 2.0);
+        *out = [mid, side];
+        left_right += l * r;
+        left_power += l * l;
+        right_power += r * r;
+        mid_power += mid * mid;
+        side_power += side * side;
+    }
+
+    // Spread over the whole window with a fractional step, the newest sample being the last point
+    let count = points.len();
+    for (n, point) in points.iter_mut().enumerate() {
+        let index = samples - 1 - (count - 1 - n) * samples / count;
+        *point = [left[index], right[index]];
+    }
+
+    let power = (left_power * right_power).sqrt();
+    let correlation = if power > f32::EPSILON {
+        left_right / power
+    } else {
+        0.0
+    };
+    let width = if mid_power + side_power > f32::EPSILON {
+        side_power / (mid_power + side_power)
+    } else {
+        0.0
+    };
+    [correlation, width, points.len() as f32, 0.0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(samples: usize) -> Vec<f32> {
+        (0..samples).map(|n| (n as f32 * 0.1).sin()).collect()
+    }
+
+    #[test]
+    fn identical_channels_are_mono() {
+        let wave = sine(256);
+        let mut stereo = vec![[0.0; 2]; stereo_buffer_len(256)];
+        let [correlation, width, points, _] = process(&[wave.clone(), wave.clone()], &mut stereo);
+        assert!((correlation - 1.0).abs() < 1e-5);
+        assert!(width.abs() < 1e-6);
+        assert_eq!(points, 256.0);
+        for (n, [mid, side]) in stereo[..256].iter().enumerate() {
+            assert!((mid - wave[n]).abs() < 1e-6 && side.abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn inverted_channels_are_out_of_phase() {
+        let wave = sine(256);
+        let inverted: Vec<f32> = wave.iter().map(|v| -v).collect();
+        let mut stereo = vec![[0.0; 2]; stereo_buffer_len(256)];
+        let [correlation, width, _, _] = process(&[wave, inverted], &mut stereo);
+        assert!((correlation + 1.0).abs() < 1e-5);
+        assert!((width - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn single_channel_is_both_sides() {
+        let wave = sine(64);
+        let mut stereo = vec![[0.0; 2]; stereo_buffer_len(64)];
+        let [correlation, _, _, _] = process(std::slice::from_ref(&wave), &mut stereo);
+        assert!((correlation - 1.0).abs() < 1e-5);
+        assert_eq!(stereo[64 + 10], [wave[10], wave[10]]);
+    }
+
+    #[test]
+    fn newest_sample_is_the_last_point() {
+        let left = sine(4096);
+        let right: Vec<f32> = left.iter().map(|v| v / 2.0).collect();
+        let mut stereo = vec![[0.0; 2]; stereo_buffer_len(4096)];
+        let [_, _, points, _] = process(&[left.clone(), right.clone()], &mut stereo);
+        assert_eq!(points, VECTORSCOPE_POINTS as f32);
+        assert_eq!(*stereo.last().unwrap(), [left[4095], right[4095]]);
+    }
+
+    #[test]
+    fn points_span_the_whole_window() {
+        // More samples than points, but less than twice as many
+        let left: Vec<f32> = (0..768).map(|n| n as f32).collect();
+        let mut stereo = vec![[0.0; 2]; stereo_buffer_len(768)];
+        process(&[left.clone(), left], &mut stereo);
+        let points = &stereo[768..];
+        assert_eq!(points.len(), VECTORSCOPE_POINTS);
+        assert!(points[0][0] <= 2.0, "{}", points[0][0]);
+        assert_eq!(points[VECTORSCOPE_POINTS - 1][0], 767.0);
+        for pair in points.windows(2) {
+            assert!(pair[1][0] > pair[0][0]);
+        }
+    }
+}
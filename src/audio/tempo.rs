@@ -0,0 +1,165 @@
+use std::collections::VecDeque;
+
+// Seconds of onset strength looked at by the autocorrelation
+const HISTORY: f32 = 8.0;
+// The history has to be at least this long before there is an estimate
+const MIN_HISTORY: f32 = 4.0;
+// Range of the tempo estimate
+const MIN_BPM: f32 = 60.0;
+const MAX_BPM: f32 = 180.0;
+// Tempi around this one are preferred, which keeps the estimate from jumping between double and half time
+const PREFERRED_BPM: f32 = 120.0;
+// Width of that preference, in octaves
+const PREFERENCE_WIDTH: f32 = 1.0;
+// Time constant of the bpm smoothing, in seconds
+const BPM_SMOOTHING: f32 = 2.0;
+// How much of the phase error is corrected on every detected beat
+const PHASE_CORRECTION: f32 = 0.2;
+
+/// Tempo estimation by autocorrelation of the onset strength, and a beat clock running at that tempo.
+/// The clock is pulled towards the detected beats, so its phase is 0 when a beat hits.
+pub struct TempoTracker {
+    // Onset strength updates per second
+    rate: f32,
+    history: VecDeque<f32>,
+    history_len: usize,
+    centered: Vec<f32>,
+    bpm: f32,
+    confidence: f32,
+    phase: f32,
+}
+
+impl TempoTracker {
+    /// `rate` is the number of calls to `process` per second
+    pub fn new(rate: f32) -> Self {
+        let history_len = (HISTORY * rate) as usize;
+        TempoTracker {
+            rate,
+            history: VecDeque::with_capacity(history_len),
+            history_len,
+            centered: Vec::with_capacity(history_len),
+            bpm: 0.0,
+            confidence: 0.0,
+            phase: 0.0,
+        }
+    }
+
+    /// Takes the onset strength of the newest window, and whether it was a beat.
+    /// Returns [bpm, beat phase (0..1), confidence (0..1)], all zeros until enough audio was heard.
+    pub fn process(&mut self, onset_strength: f32, beat: bool, dt: f32) -> [f32; 3] {
+        if self.history.len() == self.history_len {
+            self.history.pop_front();
+        }
+        self.history.push_back(onset_strength);
+
+        if let Some((bpm, confidence)) = self.estimate() {
+            self.bpm = if self.bpm == 0.0 {
+                bpm
+            } else {
+                self.bpm + (bpm - self.bpm) * (1.0 - (-dt / BPM_SMOOTHING).exp())
+            };
+            self.confidence = confidence;
+        }
+
+        self.phase = (self.phase + dt * self.bpm / 60.0).fract();
+        if beat && self.bpm > 0.0 {
+            // Distance to the closest expected beat, in beats
+            let error = if self.phase < 0.5 {
+                self.phase
+            } else {
+                self.phase - 1.0
+            };
+            self.phase = (self.phase - error * PHASE_CORRECTION).rem_euclid(1.0);
+        }
+        [self.bpm, self.phase, self.confidence]
+    }
+
+    fn estimate(&mut self) -> Option<(f32, f32)> {
+        if (self.history.len() as f32) < MIN_HISTORY * self.rate {
+            return None;
+        }
+        let mean = self.history.iter().sum::<f32>() / self.history.len() as f32;
+        self.centered.clear();
+        self.centered.extend(self.history.iter().map(|v| v - mean));
+
+        let autocorrelation = |lag: usize| -> f32 {
+            let centered = &self.centered;
+            let sum: f32 = centered
+                .iter()
+                .zip(&centered[lag..])
+                .map(|(a, b)| a * b)
+                .sum();
+            sum / (centered.len() - lag) as f32
+        };
+        let energy = autocorrelation(0);
+        if energy <= f32::EPSILON {
+            return None;
+        }
+
+        let lag_to_bpm = |lag: f32| 60.0 * self.rate / lag;
+        let min_lag = (60.0 * self.rate / MAX_BPM).floor().max(1.0) as usize;
+        let max_lag = (60.0 * self.rate / MIN_BPM).ceil() as usize;
+        let weighted = |lag: usize| {
+            let octaves = (lag_to_bpm(lag as f32) / PREFERRED_BPM).log2() / PREFERENCE_WIDTH;
+            autocorrelation(lag) * (-0.5 * octaves * octaves).exp()
+        };
+        let lag = (min_lag..=max_lag).max_by(|a, b| weighted(*a).total_cmp(&weighted(*b)))?;
+
+        // Parabolic interpolation, a whole number of updates is a coarse resolution for a tempo
+        let (left, center, right) = (
+            autocorrelation(lag - 1),
+            autocorrelation(lag),
+            autocorrelation(lag + 1),
+        );
+        let denominator = left - 2.0 * center + right;
+        let offset = if denominator.abs() > f32::EPSILON {
+            (0.5 * (left - right) / denominator).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        };
+        Some((
+            lag_to_bpm(lag as f32 + offset),
+            (center / energy).clamp(0.0, 1.0),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Onset strength of a click every `period` updates
+    fn clicks(tracker: &mut TempoTracker, period: usize, updates: usize, dt: f32) -> [f32; 3] {
+        let mut result = [0.0; 3];
+        for i in 0..updates {
+            let beat = i % period == 0;
+            result = tracker.process(if beat { 1.0 } else { 0.0 }, beat, dt);
+        }
+        result
+    }
+
+    #[test]
+    fn no_estimate_before_enough_history() {
+        let mut tracker = TempoTracker::new(40.0);
+        assert_eq!(clicks(&mut tracker, 20, 150, 0.025), [0.0; 3]);
+    }
+
+    #[test]
+    fn follows_a_steady_beat() {
+        // 40 updates per second, a click every half second
+        let mut tracker = TempoTracker::new(40.0);
+        let [bpm, phase, confidence] = clicks(&mut tracker, 20, 601, 0.025);
+        assert!((bpm - 120.0).abs() < 1.0, "{}", bpm);
+        assert!(confidence > 0.5, "{}", confidence);
+        // The last update was a click
+        assert!(phase.min(1.0 - phase) < 0.05, "{}", phase);
+    }
+
+    #[test]
+    fn prefers_tempi_near_120() {
+        // Clicks at 240 bpm are out of range, the estimate settles on half time
+        let mut tracker = TempoTracker::new(40.0);
+        let [bpm, _, _] = clicks(&mut tracker, 10, 600, 0.025);
+        assert!((bpm - 120.0).abs() < 1.0, "{}", bpm);
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    path::Path,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use hound::{SampleFormat, WavReader};
+
+use super::{AudioError, AudioSource};
+
+/// Plays a wav file in a loop, in real time, without needing a sound server
+pub struct WavSource {
+    // The whole file, interleaved
+    samples: Vec<f32>,
+    position: usize,
+    channels: usize,
+    sample_rate: u32,
+    start: Instant,
+    frames_read: u64,
+}
+
+impl WavSource {
+    pub fn open(path: &Path) -> Result<Self, hound::Error> {
+        let mut reader = WavReader::open(path)?;
+        let spec = reader.spec();
+        let samples = match spec.sample_format {
+            SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?,
+            SampleFormat::Int => {
+                // Scale the integers into -1..1
+                let max = (1i64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|s| s.map(|s| s as f32 / max))
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+        };
+        if samples.is_empty() {
+            return Err(hound::Error::FormatError("the file has no samples"));
+        }
+
+        Ok(WavSource {
+            samples,
+            position: 0,
+            channels: spec.channels as usize,
+            sample_rate: spec.sample_rate,
+            start: Instant::now(),
+            frames_read: 0,
+        })
+    }
+}
+
+impl AudioSource for WavSource {
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn read(&mut self, data: &mut [f32]) -> Result<(), AudioError> {
+        // Start the clock on the first read, not when the file was opened
+        if self.frames_read == 0 {
+            self.start = Instant::now();
+        }
+        for sample in data.iter_mut() {
+            *sample = self.samples[self.position];
+            self.position = (self.position + 1) % self.samples.len();
+        }
+
+        // Wait until the samples would have been played, like a real device would
+        self.frames_read += (data.len() / self.channels) as u64;
+        let due = Duration::from_secs_f64(self.frames_read as f64 / self.sample_rate as f64);
+        if let Some(wait) = due.checked_sub(self.start.elapsed()) {
+            sleep(wait);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hound::{WavSpec, WavWriter};
+    use std::path::PathBuf;
+
+    fn write_wav(name: &str, spec: WavSpec, samples: &[i16]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("wgpu-paper-{}-{}.wav", name, std::process::id()));
+        let mut writer = WavWriter::create(&path, spec).unwrap();
+        for sample in samples {
+            writer.write_sample(*sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        path
+    }
+
+    #[test]
+    fn loops_over_the_file() {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let path = write_wav("loop", spec, &[16384, -16384, -32768, 0]);
+        let mut source = WavSource::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(source.channels(), 2);
+        assert_eq!(source.sample_rate(), 8000);
+
+        let mut data = [0.0; 6];
+        source.read(&mut data).unwrap();
+        assert_eq!(data, [0.5, -0.5, -1.0, 0.0, 0.5, -0.5]);
+        source.read(&mut data).unwrap();
+        assert_eq!(data, [-1.0, 0.0, 0.5, -0.5, -1.0, 0.0]);
+    }
+
+    #[test]
+    fn reads_in_real_time() {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 1000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let path = write_wav("pace", spec, &[0; 10]);
+        let mut source = WavSource::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let start = Instant::now();
+        let mut data = [0.0; 25];
+        for _ in 0..4 {
+            source.read(&mut data).unwrap();
+        }
+        // 100 samples at 1 kHz
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn empty_file_is_an_error() {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let path = write_wav("empty", spec, &[]);
+        let result = WavSource::open(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,59 @@
+use std::f32::consts::TAU;
+
+/// Window applied to the samples before the fft, to reduce the spectral leakage
+#[derive(Clone, Copy)]
+pub enum WindowFunction {
+    Rectangular,
+    Hann,
+    Hamming,
+    BlackmanHarris,
+}
+
+impl WindowFunction {
+    pub fn coefficients(&self, size: usize) -> Vec<f32> {
+        let cosine_sum = |a: &[f32]| -> Vec<f32> {
+            (0..size)
+                .map(|n| {
+                    let x = TAU * n as f32 / size as f32;
+                    a.iter()
+                        .enumerate()
+                        .map(|(k, a_k)| {
+                            let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+                            sign * a_k * (k as f32 * x).cos()
+                        })
+                        .sum()
+                })
+                .collect()
+        };
+        match self {
+            WindowFunction::Rectangular => vec![1.0; size],
+            WindowFunction::Hann => cosine_sum(&[0.5, 0.5]),
+            WindowFunction::Hamming => cosine_sum(&[0.54, 0.46]),
+            WindowFunction::BlackmanHarris => cosine_sum(&[0.35875, 0.48829, 0.14128, 0.01168]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_are_periodic() {
+        let hann = WindowFunction::Hann.coefficients(8);
+        assert!(hann[0].abs() < 1e-6);
+        assert!((hann[4] - 1.0).abs() < 1e-6);
+        // Symmetric around the middle, the last sample isn't repeated
+        for n in 1..4 {
+            assert!((hann[n] - hann[8 - n]).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn window_endpoints() {
+        assert_eq!(WindowFunction::Rectangular.coefficients(4), vec![1.0; 4]);
+        assert!((WindowFunction::Hamming.coefficients(16)[0] - 0.08).abs() < 1e-6);
+        assert!(WindowFunction::BlackmanHarris.coefficients(16)[0].abs() < 1e-4);
+        assert!((WindowFunction::BlackmanHarris.coefficients(16)[8] - 1.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,250 @@
+use wgpu::{util::DeviceExt, Buffer, CommandEncoder, Device};
+
+/// Runs the FFT of the raw waveform on the gpu, the magnitudes end up in the spectrum buffer
+/// the fragment shader reads from, so the capture thread only has to upload the samples.
+pub struct GpuFft {
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+    channels: u32,
+}
+
+impl GpuFft {
+    /// `samples` has to be a power of two, at least 2
+    pub fn new(
+        device: &Device,
+        waveform_buffer: &Buffer,
+        spectrum_buffer: &Buffer,
+        window: &[f32],
+        channels: usize,
+        samples: usize,
+    ) -> Self {
+        // The buffer size gets checked when the arguments are parsed
+        debug_assert!(samples >= 2 && samples.is_power_of_two());
+
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("gpu_fft_params_buffer"),
+            contents: bytemuck::cast_slice(&[
+                samples as u32,
+                samples.trailing_zeros(),
+                (samples / 2) as u32,
+                channels as u32,
+            ]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let window_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("gpu_fft_window_buffer"),
+            contents: bytemuck::cast_slice(window),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        // Complex numbers, used as working memory for the butterflies
+        let scratch_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("gpu_fft_scratch_buffer"),
+            size: (channels.max(1) * samples * 2 * std::mem::size_of::<f32>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            count: None,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                entry(0, wgpu::BufferBindingType::Uniform),
+                entry(1, wgpu::BufferBindingType::Storage { read_only: true }),
+                entry(2, wgpu::BufferBindingType::Storage { read_only: false }),
+                entry(3, wgpu::BufferBindingType::Storage { read_only: false }),
+                entry(4, wgpu::BufferBindingType::Storage { read_only: true }),
+            ],
+            label: Some("gpu_fft_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: waveform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: scratch_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: spectrum_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: window_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("gpu_fft_bind_group"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("GPU FFT Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("gpu_fft.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("GPU FFT Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("GPU FFT Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+        });
+
+        GpuFft {
+            pipeline,
+            bind_group,
+            channels: channels as u32,
+        }
+    }
+
+    /// Records the FFT of the current content of the waveform buffer
+    pub fn dispatch(&self, encoder: &mut CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("GPU FFT Pass"),
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        // One workgroup per channel
+        compute_pass.dispatch_workgroups(self.channels, 1, 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GpuFft;
+    use crate::audio::WindowFunction;
+    use rustfft::{num_complex::Complex, FftPlanner};
+    use wgpu::util::DeviceExt;
+
+    const CHANNELS: usize = 2;
+    const SAMPLES: usize = 1024;
+
+    // A few sines and a bit of deterministic noise, different on every channel
+    fn waveform() -> Vec<f32> {
+        (0..CHANNELS * SAMPLES)
+            .map(|i| {
+                let (channel, n) = ((i / SAMPLES) as f32, (i % SAMPLES) as f32);
+                let t = std::f32::consts::TAU * n / SAMPLES as f32;
+                (t * (17.0 + channel)).sin()
+                    + 0.5 * (t * 100.3).cos()
+                    + 0.1 * ((n * 12.9898 + channel).sin() * 43758.547).fract()
+            })
+            .collect()
+    }
+
+    fn cpu_spectrum(waveform: &[f32], window: &[f32]) -> Vec<f32> {
+        let fft = FftPlanner::<f32>::new().plan_fft_forward(SAMPLES);
+        waveform
+            .chunks_exact(SAMPLES)
+            .flat_map(|wave| {
+                let mut buffer: Vec<_> = wave
+                    .iter()
+                    .zip(window)
+                    .map(|(n, w)| Complex { re: n * w, im: 0.0 })
+                    .collect();
+                fft.process(&mut buffer);
+                buffer[..SAMPLES / 2]
+                    .iter()
+                    .map(|c| c.norm())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matches_rustfft() {
+        let instance = wgpu::Instance::default();
+        let Some(adapter) =
+            pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+                force_fallback_adapter: true,
+                ..Default::default()
+            }))
+            .or_else(|| {
+                pollster::block_on(
+                    instance.request_adapter(&wgpu::RequestAdapterOptions::default()),
+                )
+            })
+        else {
+            eprintln!("No adapter available, skipping the gpu fft test");
+            return;
+        };
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: wgpu::Features::empty(),
+                limits: wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()),
+            },
+            None,
+        ))
+        .expect("Failed to request device");
+
+        let waveform = waveform();
+        let window = WindowFunction::Hann.coefficients(SAMPLES);
+        let bins = SAMPLES / 2;
+        let size = (CHANNELS * bins * std::mem::size_of::<f32>()) as u64;
+        let waveform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&waveform),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let spectrum_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let read_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let gpu_fft = GpuFft::new(
+            &device,
+            &waveform_buffer,
+            &spectrum_buffer,
+            &window,
+            CHANNELS,
+            SAMPLES,
+        );
+        let mut encoder = device.create_command_encoder(&Default::default());
+        gpu_fft.dispatch(&mut encoder);
+        encoder.copy_buffer_to_buffer(&spectrum_buffer, 0, &read_buffer, 0, size);
+        queue.submit(Some(encoder.finish()));
+
+        let slice = read_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        device.poll(wgpu::Maintain::Wait);
+        let gpu: Vec<f32> = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+
+        let cpu = cpu_spectrum(&waveform, &window);
+        let peak = cpu.iter().fold(0.0f32, |max, m| max.max(*m));
+        for (bin, (gpu, cpu)) in gpu.iter().zip(&cpu).enumerate() {
+            assert!(
+                (gpu - cpu).abs() <= 1e-4 * peak,
+                "bin {}: gpu {} cpu {}",
+                bin,
+                gpu,
+                cpu
+            );
+        }
+    }
+}
@@ -0,0 +1,68 @@
+// Radix-2 FFT, one workgroup per channel.
+// The whole transform runs in a single dispatch, the stages are separated by storage barriers,
+// this works since every workgroup only touches the data of its own channel.
+
+struct Params {
+    // Samples per channel, must be a power of two
+    samples: u32,
+    log2_samples: u32,
+    bins: u32,
+    channels: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+
+@group(0) @binding(1)
+var<storage, read> waveform: array<f32>;
+
+@group(0) @binding(2)
+var<storage, read_write> scratch: array<vec2<f32>>;
+
+@group(0) @binding(3)
+var<storage, read_write> spectrum: array<f32>;
+
+// Window function coefficients, applied before the transform
+@group(0) @binding(4)
+var<storage, read> window: array<f32>;
+
+const WORKGROUP_SIZE: u32 = 256u;
+const TAU: f32 = 6.283185307179586;
+
+@compute @workgroup_size(256)
+fn main(
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+) {
+    let n = params.samples;
+    let base = workgroup_id.x * n;
+
+    // Copy the windowed samples in bit reversed order
+    for (var i = local_id.x; i < n; i += WORKGROUP_SIZE) {
+        let j = reverseBits(i) >> (32u - params.log2_samples);
+        scratch[base + j] = vec2<f32>(waveform[base + i] * window[i], 0.0);
+    }
+    storageBarrier();
+
+    for (var stage = 1u; stage <= params.log2_samples; stage++) {
+        let half_size = 1u << (stage - 1u);
+        for (var k = local_id.x; k < n / 2u; k += WORKGROUP_SIZE) {
+            let pos = k % half_size;
+            let i = (k / half_size) * 2u * half_size + pos;
+            let j = i + half_size;
+
+            let angle = -TAU * f32(pos) / f32(2u * half_size);
+            let w = vec2<f32>(cos(angle), sin(angle));
+            let a = scratch[base + i];
+            let b = scratch[base + j];
+            let t = vec2<f32>(w.x * b.x - w.y * b.y, w.x * b.y + w.y * b.x);
+            scratch[base + i] = a + t;
+            scratch[base + j] = a - t;
+        }
+        storageBarrier();
+    }
+
+    for (var i = local_id.x; i < params.bins; i += WORKGROUP_SIZE) {
+        spectrum[workgroup_id.x * params.bins + i] = length(scratch[base + i]);
+    }
+}
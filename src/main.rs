@@ -1,21 +1,26 @@
-use std::{
-    path::PathBuf,
-    sync::{Arc, Mutex},
-    thread,
-};
+use std::{path::PathBuf, sync::Arc};
 
-use audio::AudioInput;
-use clap::{Parser, ValueEnum};
+use audio::{
+    AudioConfig, AudioSource, BandConfig, BandScale, BarsFormat, BarsOutput, PcmFormat, PcmSource,
+    PulseSource, ScopeTrigger, SourceOpener, WavSource, WindowFunction,
+};
+use clap::{error::ErrorKind, ArgAction, CommandFactory, Parser, Subcommand, ValueEnum};
 use paper::{Margin, Paper, PaperConfig};
 use smithay_client_toolkit::shell::wlr_layer::Anchor;
 use strum::Display;
+use textures::{TextureConfig, MAX_TEXTURES};
 mod audio;
+mod gpu_fft;
 mod paper;
+mod passes;
+mod textures;
 mod wgpu_layer;
 
 #[derive(Parser)]
-#[command(about, version)]
+#[command(about, version, args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// Name of the output (eg. HDMI-1, eDP-1)
     #[arg(long, short)]
     output_name: Option<String>,
@@ -40,30 +45,162 @@ struct Cli {
     /// Comma sperated list of corners to anchor to
     #[arg(long, short = 'A', value_delimiter = ',', default_values_t = [ArgAnchor::Bottom])]
     anchor: Vec<ArgAnchor>,
-    /// Enable audio input
-    #[arg(long, default_value_t = true)]
+    /// Capture audio while the shader reads the audio bindings (group 2), false to never capture
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
     audio_input: bool,
-    /// Audio device name
+    /// Where the audio comes from
+    #[arg(long, default_value_t = ArgAudioBackend::Pulse)]
+    audio_backend: ArgAudioBackend,
+    /// Audio device name, defaults to the monitor of the default sink (see `audio list`)
     #[arg(long)]
     audio_device: Option<String>,
+    /// Record the monitor of the --audio-device sink, or of the default one (PipeWire only)
+    #[cfg(feature = "pipewire")]
+    #[arg(long)]
+    capture_sink: bool,
+    /// Wav file played in a loop by the wav backend,
+    /// or file/fifo read by the pcm backend (- for stdin)
+    #[arg(long, required_if_eq_any([("audio_backend", "wav"), ("audio_backend", "pcm")]))]
+    audio_file: Option<PathBuf>,
+    /// Sample format of the pcm backend, interleaved and native endian
+    #[arg(long, default_value_t = ArgPcmFormat::S16)]
+    pcm_format: ArgPcmFormat,
     /// Audio device channels
-    #[arg(long, default_value_t = 2)]
-    audio_channels: usize,
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u16).range(1..=32))]
+    audio_channels: u16,
     /// Audio device sample rate
-    #[arg(long, default_value_t = 44100)]
+    #[arg(long, default_value_t = 44100, value_parser = clap::value_parser!(u32).range(1..))]
     sample_rate: u32,
-    /// Audio device buffer size
-    #[arg(long, default_value_t = 4096)]
+    /// Samples per channel of the analysed audio window, also the FFT size
+    #[arg(long, default_value_t = 4096, value_parser = clap::value_parser!(u32).range(1..))]
     buffer_size: u32,
+    /// Samples per channel read from the audio device between two analysed windows
+    #[arg(long, default_value_t = 1024, value_parser = clap::value_parser!(u32).range(1..))]
+    hop_size: u32,
+    /// Window function applied before the FFT
+    #[arg(long, default_value_t = ArgWindow::Hann)]
+    window: ArgWindow,
+    /// Number of past spectra kept in the spectrogram texture
+    #[arg(long, default_value_t = 256)]
+    spectrogram_rows: u32,
+    /// Samples per channel of the oscilloscope window, at most the buffer size
+    #[arg(long, default_value_t = 1024, value_parser = clap::value_parser!(u32).range(1..))]
+    scope_samples: u32,
+    /// How the oscilloscope window is aligned so periodic signals stand still:
+    /// on a rising zero crossing, or on the best match with the previous window
+    #[arg(long, default_value_t = ArgScopeTrigger::Rising)]
+    scope_trigger: ArgScopeTrigger,
+    /// Compute the FFT on the gpu instead of the cpu, requires a power of two buffer size
+    #[arg(long)]
+    gpu_fft: bool,
+    /// Give the shader a constant-Q spectrum between the band frequencies instead of the FFT bins
+    #[arg(long, conflicts_with = "gpu_fft")]
+    cqt: bool,
+    /// Number of constant-Q bins per octave
+    #[arg(long, default_value_t = 12, value_parser = clap::value_parser!(u32).range(1..))]
+    bins_per_octave: u32,
+    /// Number of frequency bands given to shader, at most 1024 so they fit in a uniform buffer
+    #[arg(long, default_value_t = 32, value_parser = clap::value_parser!(u32).range(1..=1024))]
+    bands: u32,
+    /// How the frequency bands are spaced
+    #[arg(long, default_value_t = ArgBandScale::Log)]
+    band_scale: ArgBandScale,
+    /// Lowest frequency of the bands and the constant-Q spectrum, in Hz
+    #[arg(long, default_value_t = 50.0, value_parser = parse_freq)]
+    min_freq: f32,
+    /// Highest frequency of the bands and the constant-Q spectrum, in Hz
+    #[arg(long, default_value_t = 10000.0, value_parser = parse_freq)]
+    max_freq: f32,
+    /// How much the spectral flux has to exceed its recent average to count as a beat
+    #[arg(long, default_value_t = 1.5)]
+    beat_sensitivity: f32,
+    /// Attack time of the spectrum and band smoothing, in milliseconds
+    #[arg(long, default_value_t = 0.0)]
+    attack: f32,
+    /// Release time of the spectrum and band smoothing, in milliseconds
+    #[arg(long, default_value_t = 0.0)]
+    release: f32,
+    /// Normalize the audio data by its peak over the last few seconds
+    #[arg(long)]
+    agc: bool,
+    /// Length of the automatic gain control window, in seconds
+    #[arg(long, default_value_t = 10.0)]
+    agc_window: f32,
+    /// Also write the band magnitudes of every update to this file or named pipe (- for stdout),
+    /// for status bars and the like
+    #[arg(long, conflicts_with = "gpu_fft")]
+    bars_output: Option<PathBuf>,
+    /// Format of --bars-output: integers from 0 to 1000 separated by ';' and a line per update,
+    /// or little endian u16 values
+    #[arg(long, default_value_t = ArgBarsFormat::Ascii)]
+    bars_format: ArgBarsFormat,
     /// Number of pointer positions given to shader
     #[arg(long, short, default_value_t = 10)]
     pointer_trail_frames: usize,
     /// Frames per second, higher values than vsync won't work
     #[arg(long, short)]
     fps: Option<u64>,
+    /// Image (png, jpeg or webp) to sample in the shader, in group 1 at binding 2n + 2 with its sampler
+    /// at 2n + 3, n counting from 0 in the order given, up to 10 of them. Takes options after the path, like
+    /// `image.png,wrap=repeat`: wrap=clamp|repeat|mirror, filter=linear|nearest, mipmaps, and linear
+    /// for data that isn't sRGB encoded
+    #[arg(long = "texture", value_name = "PATH[,OPTION...]")]
+    textures: Vec<TextureConfig>,
     /// Path to wgsl shader
-    #[arg(value_name = "SHADER")]
-    shader_path: PathBuf,
+    #[arg(value_name = "SHADER", required = true)]
+    shader_path: Option<PathBuf>,
+}
+
+// A frequency in Hz, the bands and the constant-Q bins need at least 1
+fn parse_freq(s: &str) -> Result<f32, String> {
+    let freq: f32 = s.parse().map_err(|err| format!("{}", err))?;
+    if freq.is_finite() && freq >= 1.0 {
+        Ok(freq)
+    } else {
+        Err("the frequency has to be at least 1 Hz".to_string())
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Audio device utilities
+    Audio {
+        #[command(subcommand)]
+        command: AudioCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum AudioCommand {
+    /// List the PulseAudio sources, with their descriptions
+    List,
+}
+
+fn list_audio_sources() {
+    // Most likely PulseAudio isn't running, which is worth a message but not a backtrace
+    let sources = match audio::list_sources() {
+        Ok(sources) => sources,
+        Err(err) => {
+            eprintln!("Unable to list the audio sources: {}", err);
+            std::process::exit(1);
+        }
+    };
+    let default_monitor = audio::default_monitor().ok().flatten();
+    for source in sources {
+        let mut tags = vec![];
+        if source.is_monitor {
+            tags.push("monitor");
+        }
+        if Some(&source.name) == default_monitor.as_ref() {
+            tags.push("default");
+        }
+        let tags = if tags.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", tags.join(", "))
+        };
+        println!("{}{}\n    {}", source.name, tags, source.description);
+    }
 }
 #[derive(ValueEnum, Display, Clone)]
 #[strum(serialize_all = "lowercase")]
@@ -85,16 +222,157 @@ impl From<ArgAnchor> for Anchor {
     }
 }
 
+#[derive(ValueEnum, Display, Clone)]
+#[strum(serialize_all = "lowercase")]
+enum ArgAudioBackend {
+    Pulse,
+    #[cfg(feature = "pipewire")]
+    Pipewire,
+    Wav,
+    Pcm,
+}
+
+#[derive(ValueEnum, Display, Clone)]
+#[strum(serialize_all = "lowercase")]
+enum ArgPcmFormat {
+    F32,
+    S16,
+}
+
+impl From<ArgPcmFormat> for PcmFormat {
+    fn from(other: ArgPcmFormat) -> PcmFormat {
+        match other {
+            ArgPcmFormat::F32 => PcmFormat::F32,
+            ArgPcmFormat::S16 => PcmFormat::S16,
+        }
+    }
+}
+
+#[derive(ValueEnum, Display, Clone)]
+#[strum(serialize_all = "lowercase")]
+enum ArgBandScale {
+    Log,
+    Mel,
+}
+
+impl From<ArgBandScale> for BandScale {
+    fn from(other: ArgBandScale) -> BandScale {
+        match other {
+            ArgBandScale::Log => BandScale::Log,
+            ArgBandScale::Mel => BandScale::Mel,
+        }
+    }
+}
+
+#[derive(ValueEnum, Display, Clone)]
+#[strum(serialize_all = "kebab-case")]
+enum ArgWindow {
+    Rectangular,
+    Hann,
+    Hamming,
+    BlackmanHarris,
+}
+
+impl From<ArgWindow> for WindowFunction {
+    fn from(other: ArgWindow) -> WindowFunction {
+        match other {
+            ArgWindow::Rectangular => WindowFunction::Rectangular,
+            ArgWindow::Hann => WindowFunction::Hann,
+            ArgWindow::Hamming => WindowFunction::Hamming,
+            ArgWindow::BlackmanHarris => WindowFunction::BlackmanHarris,
+        }
+    }
+}
+
+#[derive(ValueEnum, Display, Clone)]
+#[strum(serialize_all = "lowercase")]
+enum ArgScopeTrigger {
+    None,
+    Rising,
+    Correlation,
+}
+
+impl From<ArgScopeTrigger> for ScopeTrigger {
+    fn from(other: ArgScopeTrigger) -> ScopeTrigger {
+        match other {
+            ArgScopeTrigger::None => ScopeTrigger::None,
+            ArgScopeTrigger::Rising => ScopeTrigger::Rising,
+            ArgScopeTrigger::Correlation => ScopeTrigger::Correlation,
+        }
+    }
+}
+
+#[derive(ValueEnum, Display, Clone)]
+#[strum(serialize_all = "lowercase")]
+enum ArgBarsFormat {
+    Ascii,
+    Binary,
+}
+
+impl From<ArgBarsFormat> for BarsFormat {
+    fn from(other: ArgBarsFormat) -> BarsFormat {
+        match other {
+            ArgBarsFormat::Ascii => BarsFormat::Ascii,
+            ArgBarsFormat::Binary => BarsFormat::Binary,
+        }
+    }
+}
+
 fn main() {
     let mut args = Cli::parse();
 
+    if let Some(Command::Audio {
+        command: AudioCommand::List,
+    }) = args.command
+    {
+        list_audio_sources();
+        return;
+    }
+
+    // The compute shader only does radix-2
+    if args.gpu_fft && (args.buffer_size < 2 || !args.buffer_size.is_power_of_two()) {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--gpu-fft requires a --buffer-size that is a power of two",
+            )
+            .exit();
+    }
+    if args.min_freq >= args.max_freq {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--min-freq has to be lower than --max-freq",
+            )
+            .exit();
+    }
+    if args.textures.len() > MAX_TEXTURES {
+        Cli::command()
+            .error(
+                ErrorKind::TooManyValues,
+                format!("--texture can be given at most {} times", MAX_TEXTURES),
+            )
+            .exit();
+    }
+    let textures = args
+        .textures
+        .into_iter()
+        .map(|config| {
+            config.load().unwrap_or_else(|err| {
+                Cli::command()
+                    .error(ErrorKind::Io, format!("unable to load {}", err))
+                    .exit()
+            })
+        })
+        .collect();
+
     if let Some(output_name) = &args.output_name {
-        println!(
+        eprintln!(
             "The shader will be loaded as soon as {} is registered.",
             output_name
         )
     } else {
-        println!("The shader will be loaded on the first avaiable output.")
+        eprintln!("The shader will be loaded on the first avaiable output.")
     }
 
     let mut anchor: Anchor = args.anchor.remove(0).into();
@@ -102,19 +380,71 @@ fn main() {
     for ele in args.anchor {
         anchor |= ele.into();
     }
-    let mut audio_input: Option<Arc<Mutex<AudioInput>>> = None;
+    let mut audio = None;
     if args.audio_input {
-        let ai: Arc<Mutex<AudioInput>> = Arc::new(Mutex::new(AudioInput::new(
-            args.audio_device,
-            args.audio_channels,
-            args.sample_rate,
-            args.buffer_size,
-        )));
-        let cloned_ai = ai.clone();
-        thread::spawn(|| {
-            AudioInput::start_capture_loop(cloned_ai);
+        // Called whenever the capture starts, and to reopen the source after it failed
+        let open: SourceOpener = Arc::new(move || {
+            let source: Box<dyn AudioSource> = match args.audio_backend {
+                ArgAudioBackend::Pulse => Box::new(PulseSource::new(
+                    // Record what is playing, not the microphone, unless told otherwise
+                    args.audio_device
+                        .clone()
+                        .or_else(|| audio::default_monitor().ok().flatten())
+                        .as_deref(),
+                    args.audio_channels.into(),
+                    args.sample_rate,
+                    args.hop_size as usize,
+                )?),
+                #[cfg(feature = "pipewire")]
+                ArgAudioBackend::Pipewire => Box::new(audio::PipewireSource::new(
+                    args.audio_device.clone(),
+                    args.capture_sink,
+                    args.audio_channels.into(),
+                    args.sample_rate,
+                )),
+                ArgAudioBackend::Wav => Box::new(WavSource::open(
+                    args.audio_file
+                        .as_ref()
+                        .expect("clap requires --audio-file for the wav backend"),
+                )?),
+                ArgAudioBackend::Pcm => Box::new(PcmSource::open(
+                    args.audio_file
+                        .clone()
+                        .expect("clap requires --audio-file for the pcm backend"),
+                    args.pcm_format.clone().into(),
+                    args.audio_channels.into(),
+                    args.sample_rate,
+                )?),
+            };
+            Ok(source)
         });
-        audio_input = Some(ai);
+        let config = AudioConfig {
+            channels: args.audio_channels.into(),
+            sample_rate: args.sample_rate,
+            buffer_size: args.buffer_size,
+            hop_size: args.hop_size,
+            window: args.window.into(),
+            gpu_fft: args.gpu_fft,
+            cqt_bins_per_octave: args.cqt.then_some(args.bins_per_octave),
+            spectrogram_rows: args.spectrogram_rows,
+            scope_samples: args.scope_samples,
+            scope_trigger: args.scope_trigger.into(),
+            bands: BandConfig {
+                scale: args.band_scale.into(),
+                count: args.bands as usize,
+                min_freq: args.min_freq,
+                max_freq: args.max_freq,
+            },
+            beat_sensitivity: args.beat_sensitivity,
+            attack: args.attack / 1000.0,
+            release: args.release / 1000.0,
+            agc_window: args.agc.then_some(args.agc_window),
+            bars_output: args.bars_output.map(|path| BarsOutput {
+                path,
+                format: args.bars_format.into(),
+            }),
+        };
+        audio = Some((config, open));
     }
 
     Paper::run(PaperConfig {
@@ -128,9 +458,10 @@ fn main() {
             bottom: args.margin_bottom,
             left: args.margin_left,
         },
-        audio_input,
+        audio,
         pointer_trail_frames: args.pointer_trail_frames,
         fps: args.fps,
-        shader_path: args.shader_path,
+        shader_path: args.shader_path.unwrap(),
+        textures,
     });
 }
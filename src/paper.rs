@@ -25,24 +25,37 @@ use wayland_client::{
 use std::{
     fs,
     path::PathBuf,
-    sync::{Arc, Mutex},
     thread::sleep,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 
+// Bind group of the audio data
+const AUDIO_GROUP: u32 = 2;
+// How often the shader file is checked for changes
+const SHADER_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct PaperConfig {
     pub output_name: Option<String>,
     pub width: Option<u32>,
     pub height: Option<u32>,
     pub anchor: Anchor,
     pub margin: Margin,
-    pub audio_input: Option<Arc<Mutex<AudioInput>>>,
+    // Capture settings, None if audio input is disabled
+    pub audio: Option<(AudioConfig, SourceOpener)>,
     pub pointer_trail_frames: usize,
     pub fps: Option<u64>,
     pub shader_path: PathBuf,
+    // Decoded at startup, every output uploads them
+    pub textures: Vec<Texture>,
 }
 
-use crate::{audio::AudioInput, wgpu_layer::*};
+use crate::{
+    audio::{scope_samples, spectrum_bins, AudioConfig, AudioInput, SourceOpener},
+    gpu_fft::GpuFft,
+    passes::Passes,
+    textures::{texture_entries, upload_textures, Texture},
+    wgpu_layer::*,
+};
 pub struct Paper {
     pub registry_state: RegistryState,
     pub seat_state: SeatState,
@@ -58,11 +71,17 @@ pub struct Paper {
     pub margin: Margin,
 
     pub shader_path: PathBuf,
+    pub shader_modified: Option<SystemTime>,
+    pub last_shader_poll: Instant,
     pub output_name: Option<String>,
     pub fps: Option<u64>,
     pub last_frame: Instant,
 
-    pub audio_input: Option<Arc<Mutex<AudioInput>>>,
+    pub audio: Option<(AudioConfig, SourceOpener)>,
+    // Only running while the shader reads the audio bindings
+    pub audio_input: Option<AudioInput>,
+
+    pub textures: Vec<Texture>,
 
     pub pointer: Option<wl_pointer::WlPointer>,
     pub pointer_positions: Vec<[f32; 4]>,
@@ -96,6 +115,8 @@ impl Paper {
             anchor: config.anchor,
             margin: config.margin,
             shader_path: config.shader_path,
+            shader_modified: None,
+            last_shader_poll: Instant::now(),
             output_name: config.output_name,
             fps: config.fps,
             last_frame: Instant::now(),
@@ -109,14 +130,18 @@ impl Paper {
             pointer_positions: vec![[-100.0, -100.0, 0.0, 0.0]; config.pointer_trail_frames],
             current_pointer_pos: None,
             wgpu_layer: None,
-            audio_input: config.audio_input,
+            audio: config.audio,
+            audio_input: None,
+            textures: config.textures,
         };
+        // The bars get exported whatever the shader is
+        paper.update_audio_capture(false);
 
         loop {
             event_queue.blocking_dispatch(&mut paper).unwrap();
 
             if paper.exit {
-                println!("exiting example");
+                eprintln!("exiting example");
                 break;
             }
         }
@@ -155,6 +180,8 @@ impl OutputHandler for Paper {
         // Load the shader
         let shader_data =
             fs::read_to_string(self.shader_path.clone()).expect("Unable to read file");
+        let shader_module = parse_shader(&shader_data).unwrap_or_else(|err| panic!("{}", err));
+        self.shader_modified = shader_modified(&self.shader_path);
 
         let compositor =
             CompositorState::bind(&self.globals, &self.qh).expect("wl_compositor is not available");
@@ -253,8 +280,8 @@ impl OutputHandler for Paper {
                 label: None,
                 features: wgpu::Features::empty(),
                 // Make sure we use the texture resolution limits from the adapter, so we can support images the size of the swapchain.
-                limits:
-                    wgpu::Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits()),
+                // The webgl2 defaults don't allow storage buffers, which are needed for the audio data.
+                limits: wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()),
             },
             None,
         ))
@@ -270,51 +297,36 @@ impl OutputHandler for Paper {
                 0,
                 bytemuck::cast_slice(&[0.0f32]),
                 false,
+                Default::default(),
             );
-        /* -- Pointer pos buffer, binding: 1 -- */
+        /* -- Pointer pos buffer, binding: 1, and the images after it -- */
+        let textures = upload_textures(&device, &queue, &self.textures);
         let (pointer_buffer, pointer_group_layout, pointer_bind_group) = create_gpu_buffer(
             &device,
             "pointer",
             1,
             bytemuck::cast_slice(self.pointer_positions.as_slice()),
             false,
+            texture_entries(&textures),
         );
+        /* -- Audio buffers, group: 2 -- */
+        self.update_audio_capture(uses_audio(&shader_module));
+        let (audio_buffers, audio_group_layout, audio_bind_group, gpu_fft) =
+            create_audio_resources(&device, self.audio_config());
 
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(shader_data.into()),
-        });
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&elapsed_time_group_layout, &pointer_group_layout],
-                push_constant_ranges: &[],
-            });
-
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                // Add the surface target
-                targets: &[Some(wgpu::ColorTargetState {
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                    format: surface.get_capabilities(&adapter).formats[0],
-                })],
-            }),
-            primitive: Default::default(),
-            depth_stencil: Default::default(),
-            multisample: Default::default(),
-            multiview: Default::default(),
-        });
+        /* -- Buffer passes, group: 3 -- */
+        let (passes, render_pipeline) = create_shader_pipelines(
+            &device,
+            surface.get_capabilities(&adapter).formats[0],
+            [
+                &elapsed_time_group_layout,
+                &pointer_group_layout,
+                &audio_group_layout,
+            ],
+            shader_data,
+            &shader_module,
+            (self.width.unwrap(), self.height.unwrap()),
+        );
 
         self.wgpu_layer = Some(WgpuLayer {
             start_time: Instant::now(),
@@ -324,10 +336,17 @@ impl OutputHandler for Paper {
             queue,
             surface,
             render_pipeline,
+            elapsed_time_group_layout,
             elapsed_time_bind_group,
             elapsed_time_buffer,
+            pointer_group_layout,
             pointer_bind_group,
             pointer_buffer,
+            audio_group_layout,
+            audio_bind_group,
+            audio_buffers,
+            gpu_fft,
+            passes,
         })
     }
 
@@ -348,12 +367,235 @@ impl OutputHandler for Paper {
     }
 }
 
+// Buffers sized for the audio format, empty ones without audio input
+fn create_audio_resources(
+    device: &wgpu::Device,
+    config: Option<&AudioConfig>,
+) -> (
+    AudioBuffers,
+    wgpu::BindGroupLayout,
+    wgpu::BindGroup,
+    Option<GpuFft>,
+) {
+    let (channels, samples, bins, bands, spectrogram_rows, scope_samples) = match config {
+        Some(config) => (
+            config.channels,
+            config.buffer_size as usize,
+            spectrum_bins(config),
+            config.bands.count,
+            config.spectrogram_rows,
+            scope_samples(config),
+        ),
+        None => (0, 0, 0, 0, 0, 0),
+    };
+    let limits = device.limits();
+    // The spectrogram has a texel per bin and a layer per channel, neither can be cut without losing data
+    if bins as u32 > limits.max_texture_dimension_2d {
+        eprintln!(
+            "The spectrogram needs {} bins, more than the {} the GPU supports, use a smaller --buffer-size",
+            bins, limits.max_texture_dimension_2d
+        );
+        std::process::exit(1);
+    }
+    if channels as u32 > limits.max_texture_array_layers {
+        eprintln!(
+            "The spectrogram needs a layer for each of the {} channels, the GPU supports at most {}",
+            channels, limits.max_texture_array_layers
+        );
+        std::process::exit(1);
+    }
+    let spectrogram_rows = if spectrogram_rows > limits.max_texture_dimension_2d {
+        eprintln!(
+            "{} spectrogram rows are more than the {} the GPU supports, only {} are kept",
+            spectrogram_rows, limits.max_texture_dimension_2d, limits.max_texture_dimension_2d
+        );
+        limits.max_texture_dimension_2d
+    } else {
+        spectrogram_rows
+    };
+    let (audio_buffers, audio_group_layout, audio_bind_group) = create_audio_buffers(
+        device,
+        channels,
+        samples,
+        bins,
+        bands,
+        spectrogram_rows,
+        scope_samples,
+    );
+    let gpu_fft = config.and_then(|config| {
+        config.gpu_fft.then(|| {
+            GpuFft::new(
+                device,
+                &audio_buffers.waveform_buffer,
+                &audio_buffers.spectrum_buffer,
+                &config.window.coefficients(samples),
+                channels,
+                samples,
+            )
+        })
+    });
+    (audio_buffers, audio_group_layout, audio_bind_group, gpu_fft)
+}
+
+// The buffer passes the shader declares and the pipeline of its main pass
+fn create_shader_pipelines(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    bind_group_layouts: [&wgpu::BindGroupLayout; 3],
+    shader_data: String,
+    shader_module: &naga::Module,
+    (width, height): (u32, u32),
+) -> (Passes, wgpu::RenderPipeline) {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Shader"),
+        source: wgpu::ShaderSource::Wgsl(shader_data.into()),
+    });
+
+    let mut passes = Passes::new(device, shader_module, format, width, height);
+    passes.create_pipelines(device, bind_group_layouts, &shader);
+    let [time, pointer, audio] = bind_group_layouts;
+    let render_pipeline = create_render_pipeline(
+        device,
+        passes.main_format(format),
+        &[time, pointer, audio, &passes.layout],
+        &shader,
+        "fs_main",
+    );
+    (passes, render_pipeline)
+}
+
+fn shader_modified(path: &PathBuf) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// Whether the shader reads any of the audio bindings
+fn uses_audio(shader_module: &naga::Module) -> bool {
+    uses_binding(shader_module, |resource| resource.group == AUDIO_GROUP)
+}
+
+/// Starts or stops the audio capture, it only runs while the shader reads the audio bindings
+/// or the bars get exported. Returns whether it changed.
+fn update_audio_capture(
+    audio_input: &mut Option<AudioInput>,
+    audio: Option<&(AudioConfig, SourceOpener)>,
+    shader_uses_audio: bool,
+) -> bool {
+    let Some((config, open)) = audio else {
+        return false;
+    };
+    let wanted = shader_uses_audio || config.bars_output.is_some();
+    match (&audio_input, wanted) {
+        (None, true) => {
+            *audio_input = Some(AudioInput::start(config.clone(), open.clone()));
+            true
+        }
+        (Some(_), false) => {
+            eprintln!("The shader doesn't use the audio bindings, audio capture stopped");
+            // Waits for the capture thread, so the next start never reads next to it
+            *audio_input = None;
+            true
+        }
+        _ => false,
+    }
+}
+
 impl Paper {
+    // The format of the running capture, the source may have changed it.
+    // Without a capture the shader still gets buffers of the configured size.
+    fn audio_config(&self) -> Option<&AudioConfig> {
+        match &self.audio_input {
+            Some(ai) => Some(&ai.config),
+            None => self.audio.as_ref().map(|(config, _)| config),
+        }
+    }
+
+    fn update_audio_capture(&mut self, shader_uses_audio: bool) -> bool {
+        update_audio_capture(
+            &mut self.audio_input,
+            self.audio.as_ref(),
+            shader_uses_audio,
+        )
+    }
+
+    // Loads the shader again once the file changed. A broken shader is reported and the old one keeps running.
+    fn reload_shader(&mut self) {
+        if self.last_shader_poll.elapsed() < SHADER_POLL_INTERVAL {
+            return;
+        }
+        self.last_shader_poll = Instant::now();
+        let modified = shader_modified(&self.shader_path);
+        if modified == self.shader_modified {
+            return;
+        }
+        self.shader_modified = modified;
+
+        let shader_data = match fs::read_to_string(&self.shader_path) {
+            Ok(shader_data) => shader_data,
+            Err(err) => {
+                eprintln!("Unable to read {}: {}", self.shader_path.display(), err);
+                return;
+            }
+        };
+        let shader_module = match parse_shader(&shader_data) {
+            Ok(shader_module) => shader_module,
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            }
+        };
+
+        // Layouts with the same entries are interchangeable, so the pipelines can be checked
+        // before the audio buffers for the new shader exist
+        let Some(wgpu_layer) = &self.wgpu_layer else {
+            return;
+        };
+        wgpu_layer
+            .device
+            .push_error_scope(wgpu::ErrorFilter::Validation);
+        let (passes, render_pipeline) = create_shader_pipelines(
+            &wgpu_layer.device,
+            wgpu_layer
+                .surface
+                .get_capabilities(&wgpu_layer.adapter)
+                .formats[0],
+            wgpu_layer.shared_layouts(),
+            shader_data,
+            &shader_module,
+            (self.width.unwrap(), self.height.unwrap()),
+        );
+        if let Some(err) = pollster::block_on(wgpu_layer.device.pop_error_scope()) {
+            eprintln!("Unable to load {}: {}", self.shader_path.display(), err);
+            return;
+        }
+
+        // A new capture may have a different format than the buffers were made for
+        if self.update_audio_capture(uses_audio(&shader_module)) {
+            let resources = create_audio_resources(
+                &self.wgpu_layer.as_ref().unwrap().device,
+                self.audio_config(),
+            );
+            let wgpu_layer = self.wgpu_layer.as_mut().unwrap();
+            (
+                wgpu_layer.audio_buffers,
+                wgpu_layer.audio_group_layout,
+                wgpu_layer.audio_bind_group,
+                wgpu_layer.gpu_fft,
+            ) = resources;
+        }
+        let wgpu_layer = self.wgpu_layer.as_mut().unwrap();
+        wgpu_layer.render_pipeline = render_pipeline;
+        wgpu_layer.passes = passes;
+        eprintln!("Reloaded {}", self.shader_path.display());
+    }
+
     pub fn draw(&mut self, qh: &QueueHandle<Self>) {
         if self.wgpu_layer.is_none() {
             return;
         };
-        let wgpu_layer = self.wgpu_layer.as_ref().unwrap();
+        self.reload_shader();
+        let wgpu_layer = self.wgpu_layer.as_mut().unwrap();
         let surface_texture = wgpu_layer
             .surface
             .get_current_texture()
@@ -363,16 +605,138 @@ impl Paper {
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
 
+        // Upload the newest audio data, only if the capture thread has published a new frame
+        let mut new_audio = false;
+        if let Some(ai) = &mut self.audio_input {
+            let audio_buffers = &wgpu_layer.audio_buffers;
+            let samples = ai.config.buffer_size as usize;
+            let bins = spectrum_bins(&ai.config);
+            let info = [
+                ai.config.channels as u32,
+                samples as u32,
+                bins as u32,
+                ai.config.sample_rate,
+            ];
+            if let Some(frame) = ai.new_frame() {
+                wgpu_layer.queue.write_buffer(
+                    &audio_buffers.info_buffer,
+                    0,
+                    bytemuck::cast_slice(&info),
+                );
+                // Every channel gets its own fixed size slice of the buffer
+                for (channel, wave) in frame.audio_buffers.iter().enumerate() {
+                    wgpu_layer.queue.write_buffer(
+                        &audio_buffers.waveform_buffer,
+                        (channel * samples * std::mem::size_of::<f32>()) as u64,
+                        bytemuck::cast_slice(wave),
+                    );
+                }
+                for (channel, fft) in frame.fft_buffers.iter().enumerate() {
+                    wgpu_layer.queue.write_buffer(
+                        &audio_buffers.spectrum_buffer,
+                        (channel * bins * std::mem::size_of::<f32>()) as u64,
+                        bytemuck::cast_slice(fft),
+                    );
+                }
+                wgpu_layer.queue.write_buffer(
+                    &audio_buffers.bands_buffer,
+                    0,
+                    bytemuck::cast_slice(frame.bands.as_slice()),
+                );
+                wgpu_layer.queue.write_buffer(
+                    &audio_buffers.levels_buffer,
+                    0,
+                    bytemuck::cast_slice(frame.levels.as_slice()),
+                );
+                wgpu_layer.queue.write_buffer(
+                    &audio_buffers.pitch_buffer,
+                    0,
+                    bytemuck::cast_slice(&frame.pitch),
+                );
+                wgpu_layer.queue.write_buffer(
+                    &audio_buffers.features_buffer,
+                    0,
+                    bytemuck::cast_slice(frame.features.as_slice()),
+                );
+                wgpu_layer.queue.write_buffer(
+                    &audio_buffers.stereo_buffer,
+                    0,
+                    bytemuck::cast_slice(frame.stereo.as_slice()),
+                );
+                wgpu_layer.queue.write_buffer(
+                    &audio_buffers.scope_buffer,
+                    0,
+                    bytemuck::cast_slice(frame.scope.as_slice()),
+                );
+                wgpu_layer.queue.write_buffer(
+                    &audio_buffers.stereo_info_buffer,
+                    0,
+                    bytemuck::cast_slice(&frame.stereo_info),
+                );
+                new_audio = true;
+            }
+            // Written every frame, so the time since the last beat advances smoothly
+            let frame = ai.frame();
+            let since_beat = frame.last_beat.unwrap_or(wgpu_layer.start_time).elapsed();
+            // Keep the beat clock running between the updates
+            let [bpm, phase, confidence] = frame.tempo;
+            let phase = (phase + frame.tempo_time.elapsed().as_secs_f32() * bpm / 60.0).fract();
+            wgpu_layer.queue.write_buffer(
+                &audio_buffers.beat_buffer,
+                0,
+                bytemuck::cast_slice(&[
+                    since_beat.as_secs_f32(),
+                    frame.beat_count as f32,
+                    frame.onset_strength,
+                    0.0,
+                    bpm,
+                    phase,
+                    confidence,
+                    0.0,
+                ]),
+            );
+            let (available, since_change) = ai.availability();
+            wgpu_layer.queue.write_buffer(
+                &audio_buffers.status_buffer,
+                0,
+                bytemuck::cast_slice(&[
+                    if available { 1.0f32 } else { 0.0 },
+                    since_change.as_secs_f32(),
+                    0.0,
+                    0.0,
+                ]),
+            );
+        }
+
         let mut encoder = wgpu_layer
             .device
             .create_command_encoder(&Default::default());
+        // The spectrum has to be ready before the render passes read it
+        if new_audio {
+            if let Some(gpu_fft) = &wgpu_layer.gpu_fft {
+                gpu_fft.dispatch(&mut encoder);
+            }
+            push_spectrogram_row(
+                &wgpu_layer.queue,
+                &mut encoder,
+                &mut wgpu_layer.audio_buffers,
+            );
+        }
+        wgpu_layer.passes.render(
+            &mut encoder,
+            [
+                &wgpu_layer.elapsed_time_bind_group,
+                &wgpu_layer.pointer_bind_group,
+                &wgpu_layer.audio_bind_group,
+            ],
+        );
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[
                     // This is what @location(0) in the fragment shader targets
                     Some(wgpu::RenderPassColorAttachment {
-                        view: &texture_view,
+                        view: wgpu_layer.passes.main_target(&texture_view),
                         resolve_target: None,
                         ops: wgpu::Operations {
                             load: wgpu::LoadOp::Clear(wgpu::Color::default()),
@@ -387,9 +751,12 @@ impl Paper {
 
             render_pass.set_bind_group(0, &wgpu_layer.elapsed_time_bind_group, &[]);
             render_pass.set_bind_group(1, &wgpu_layer.pointer_bind_group, &[]);
+            render_pass.set_bind_group(2, &wgpu_layer.audio_bind_group, &[]);
+            render_pass.set_bind_group(3, wgpu_layer.passes.main_bind_group(), &[]);
 
             render_pass.draw(0..3, 0..1);
         }
+        wgpu_layer.passes.present(&mut encoder, &texture_view);
 
         // Submit the command in the queue to execute
         wgpu_layer.queue.write_buffer(
@@ -429,3 +796,100 @@ impl Paper {
             .frame(qh, wgpu_layer.layer.wl_surface().clone());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{tests::config, AudioError, AudioSource};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    // Silence at the pace of a device, counting how many of them are open
+    struct CountedSource(Arc<AtomicUsize>);
+
+    impl AudioSource for CountedSource {
+        fn channels(&self) -> usize {
+            2
+        }
+
+        fn sample_rate(&self) -> u32 {
+            44100
+        }
+
+        fn read(&mut self, data: &mut [f32]) -> Result<(), AudioError> {
+            sleep(Duration::from_millis(5));
+            data.fill(0.0);
+            Ok(())
+        }
+    }
+
+    impl Drop for CountedSource {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn capture_follows_the_shader() {
+        let audio_shader = parse_shader(
+            "@group(2) @binding(2) var<storage, read> spectrum: array<f32>;
+            @fragment
+            fn fs_main() -> @location(0) vec4<f32> {
+                return vec4<f32>(spectrum[0]);
+            }",
+        )
+        .unwrap();
+        let plain_shader = parse_shader(
+            "@group(0) @binding(0) var<uniform> time: f32;
+            @fragment
+            fn fs_main() -> @location(0) vec4<f32> {
+                return vec4<f32>(sin(time));
+            }",
+        )
+        .unwrap();
+        assert!(uses_audio(&audio_shader));
+        assert!(!uses_audio(&plain_shader));
+
+        let open_sources = Arc::new(AtomicUsize::new(0));
+        let counter = open_sources.clone();
+        let open: SourceOpener = Arc::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(Box::new(CountedSource(counter.clone())) as Box<dyn AudioSource>)
+        });
+        let audio = Some((config(), open));
+        let mut audio_input = None;
+
+        assert!(update_audio_capture(
+            &mut audio_input,
+            audio.as_ref(),
+            uses_audio(&audio_shader)
+        ));
+        assert!(audio_input.is_some());
+        assert_eq!(open_sources.load(Ordering::SeqCst), 1);
+        // The same shader again leaves the running capture alone
+        assert!(!update_audio_capture(
+            &mut audio_input,
+            audio.as_ref(),
+            uses_audio(&audio_shader)
+        ));
+        assert_eq!(open_sources.load(Ordering::SeqCst), 1);
+
+        // Stopping joins the capture thread, so its source is closed right away
+        assert!(update_audio_capture(
+            &mut audio_input,
+            audio.as_ref(),
+            uses_audio(&plain_shader)
+        ));
+        assert!(audio_input.is_none());
+        assert_eq!(open_sources.load(Ordering::SeqCst), 0);
+
+        assert!(update_audio_capture(
+            &mut audio_input,
+            audio.as_ref(),
+            uses_audio(&audio_shader)
+        ));
+        assert_eq!(open_sources.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,371 @@
+use wgpu::{BindGroup, BindGroupLayout, CommandEncoder, Device, RenderPipeline, TextureView};
+
+use crate::wgpu_layer::{create_render_pipeline, uses_binding};
+
+/// Names of the buffer passes, `fs_<name>` in the shader. They are rendered in this order, before `fs_main`.
+pub const BUFFER_NAMES: [&str; 4] = ["buffer_a", "buffer_b", "buffer_c", "buffer_d"];
+// Enough precision to carry values from pass to pass and frame to frame, and still filterable
+const BUFFER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+// Group of the pass textures, and the binding of the previous output of the main pass in it
+const PASS_GROUP: u32 = 3;
+const PREVIOUS_FRAME_BINDING: u32 = 5;
+
+// Copies the output of the main pass to the surface, when it has to be kept for the next frame
+const BLIT_SHADER: &str = "
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var blit_sampler: sampler;
+
+@group(0) @binding(1)
+var blit_texture: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var result: VertexOutput;
+    let tc = vec2<f32>(f32(i32(vertex_index) / 2) * 2.0, f32(i32(vertex_index) & 1) * 2.0);
+    result.position = vec4<f32>(tc.x * 2.0 - 1.0, 1.0 - tc.y * 2.0, 0.0, 1.0);
+    result.tex_coords = tc;
+    return result;
+}
+
+@fragment
+fn fs_main(input: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(blit_texture, blit_sampler, input.tex_coords);
+}
+";
+
+/// Shadertoy style buffer passes, every one the shader declares renders into its own texture.
+/// All the passes, and the main one, sample those through group 3:
+/// a linear sampler at binding 0, buffer a to d at bindings 1 to 4,
+/// and the output of the main pass from the previous frame at binding 5.
+/// A pass sees what the earlier passes rendered this frame, and what it and the later ones rendered last frame.
+/// Every texture is a pair, one gets rendered to while the other one holds the previous frame.
+/// Buffers the shader doesn't declare read as transparent black.
+pub struct Passes {
+    pub layout: BindGroupLayout,
+    sampler: wgpu::Sampler,
+    // Bound in place of the missing buffers
+    placeholder: TextureView,
+    // Indices into BUFFER_NAMES of the declared passes, in order
+    buffers: Vec<usize>,
+    // Render targets of every declared pass, the one written this frame is `views[pass][frame]`
+    views: Vec<[TextureView; 2]>,
+    // Render targets of the main pass, only when the shader reads its previous frame
+    main_views: Option<[TextureView; 2]>,
+    // Group 3 of every declared pass, then of the main pass, for both halves of the pairs
+    bind_groups: [Vec<BindGroup>; 2],
+    pipelines: Vec<RenderPipeline>,
+    blit: Option<Blit>,
+    frame: usize,
+    size: Option<(u32, u32)>,
+}
+
+// The pipeline copying the main pass to the surface, and its bind groups for both halves
+struct Blit {
+    layout: BindGroupLayout,
+    pipeline: RenderPipeline,
+    bind_groups: Vec<BindGroup>,
+}
+
+impl Passes {
+    /// Finds the passes declared by the shader, their textures are sized like the surface
+    pub fn new(
+        device: &Device,
+        module: &naga::Module,
+        surface_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let buffers = BUFFER_NAMES
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| {
+                module.entry_points.iter().any(|entry| {
+                    entry.stage == naga::ShaderStage::Fragment && entry.name == entry_point(name)
+                })
+            })
+            .map(|(buffer, _)| buffer)
+            .collect();
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let sampler_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("passes_group_layout"),
+            entries: &[
+                sampler_entry,
+                texture_entry(1),
+                texture_entry(2),
+                texture_entry(3),
+                texture_entry(4),
+                texture_entry(PREVIOUS_FRAME_BINDING),
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("passes_sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        // New textures are zeroed, so this stays transparent black
+        let placeholder = create_buffer_texture(device, "passes_placeholder", 1, 1);
+
+        // Rendering the main pass somewhere else first costs a copy, only pay it when needed
+        let reads_previous_frame = uses_binding(module, |resource| {
+            resource.group == PASS_GROUP && resource.binding == PREVIOUS_FRAME_BINDING
+        });
+        let blit = reads_previous_frame.then(|| {
+            let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("blit_group_layout"),
+                entries: &[sampler_entry, texture_entry(1)],
+            });
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Blit Shader"),
+                source: wgpu::ShaderSource::Wgsl(BLIT_SHADER.into()),
+            });
+            let pipeline =
+                create_render_pipeline(device, surface_format, &[&layout], &shader, "fs_main");
+            Blit {
+                layout,
+                pipeline,
+                bind_groups: vec![],
+            }
+        });
+
+        let mut passes = Passes {
+            layout,
+            sampler,
+            placeholder,
+            buffers,
+            views: vec![],
+            main_views: None,
+            bind_groups: [vec![], vec![]],
+            pipelines: vec![],
+            blit,
+            frame: 0,
+            size: None,
+        };
+        passes.resize(device, width, height);
+        passes
+    }
+
+    /// Recreates the textures in the new size, what they held is lost
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        // Configure also comes without a new size, that shouldn't wipe the feedback
+        if self.size == Some((width, height)) {
+            return;
+        }
+        self.size = Some((width, height));
+
+        let create_pair = |label: &str| {
+            [0, 1].map(|half| {
+                create_buffer_texture(device, &format!("{}_{}", label, half), width, height)
+            })
+        };
+        self.views = self
+            .buffers
+            .iter()
+            .map(|buffer| create_pair(BUFFER_NAMES[*buffer]))
+            .collect();
+        self.main_views = self.blit.is_some().then(|| create_pair("main"));
+
+        self.bind_groups = [0, 1].map(|frame| {
+            // The main pass is the one without an own buffer, it comes after all of them
+            let owners = self.buffers.iter().copied().chain([BUFFER_NAMES.len()]);
+            owners
+                .map(|owner| {
+                    let mut entries = vec![wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    }];
+                    for buffer in 0..BUFFER_NAMES.len() {
+                        let view =
+                            match self.buffers.iter().position(|declared| *declared == buffer) {
+                                // Already rendered this frame
+                                Some(pass) if buffer < owner => &self.views[pass][frame],
+                                // Not yet, so what it rendered last frame
+                                Some(pass) => &self.views[pass][1 - frame],
+                                None => &self.placeholder,
+                            };
+                        entries.push(wgpu::BindGroupEntry {
+                            binding: buffer as u32 + 1,
+                            resource: wgpu::BindingResource::TextureView(view),
+                        });
+                    }
+                    let previous = match &self.main_views {
+                        Some(main_views) => &main_views[1 - frame],
+                        None => &self.placeholder,
+                    };
+                    entries.push(wgpu::BindGroupEntry {
+                        binding: PREVIOUS_FRAME_BINDING,
+                        resource: wgpu::BindingResource::TextureView(previous),
+                    });
+                    device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("passes_bind_group"),
+                        layout: &self.layout,
+                        entries: &entries,
+                    })
+                })
+                .collect()
+        });
+
+        if let (Some(blit), Some(main_views)) = (&mut self.blit, &self.main_views) {
+            blit.bind_groups = main_views
+                .iter()
+                .map(|view| {
+                    device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("blit_bind_group"),
+                        layout: &blit.layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: wgpu::BindingResource::Sampler(&self.sampler),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: wgpu::BindingResource::TextureView(view),
+                            },
+                        ],
+                    })
+                })
+                .collect();
+        }
+    }
+
+    /// Format the main pass renders in, the surface one unless it gets copied there
+    pub fn main_format(&self, surface_format: wgpu::TextureFormat) -> wgpu::TextureFormat {
+        match self.blit {
+            Some(_) => BUFFER_FORMAT,
+            None => surface_format,
+        }
+    }
+
+    /// Builds the pipelines of the declared passes, `bind_group_layouts` are the groups 0 to 2
+    pub fn create_pipelines(
+        &mut self,
+        device: &Device,
+        bind_group_layouts: [&BindGroupLayout; 3],
+        shader: &wgpu::ShaderModule,
+    ) {
+        let [time, pointer, audio] = bind_group_layouts;
+        let pipelines = self
+            .buffers
+            .iter()
+            .map(|buffer| {
+                create_render_pipeline(
+                    device,
+                    BUFFER_FORMAT,
+                    &[time, pointer, audio, &self.layout],
+                    shader,
+                    &entry_point(BUFFER_NAMES[*buffer]),
+                )
+            })
+            .collect();
+        self.pipelines = pipelines;
+    }
+
+    /// Group 3 of the main pass, where all the buffers can be sampled
+    pub fn main_bind_group(&self) -> &BindGroup {
+        self.bind_groups[self.frame].last().unwrap()
+    }
+
+    /// Where the main pass renders to, the surface unless it gets copied there afterwards
+    pub fn main_target<'a>(&'a self, surface_view: &'a TextureView) -> &'a TextureView {
+        match &self.main_views {
+            Some(main_views) => &main_views[self.frame],
+            None => surface_view,
+        }
+    }
+
+    /// Swaps the halves of the pairs and records the buffer passes, with `bind_groups` as the groups 0 to 2
+    pub fn render(&mut self, encoder: &mut CommandEncoder, bind_groups: [&BindGroup; 3]) {
+        self.frame = 1 - self.frame;
+        for ((views, pipeline), pass_bind_group) in self
+            .views
+            .iter()
+            .zip(&self.pipelines)
+            .zip(&self.bind_groups[self.frame])
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Buffer Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &views[self.frame],
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(pipeline);
+            for (group, bind_group) in bind_groups.into_iter().enumerate() {
+                render_pass.set_bind_group(group as u32, bind_group, &[]);
+            }
+            render_pass.set_bind_group(PASS_GROUP, pass_bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+
+    /// Copies the main pass to the surface, if it didn't render there directly
+    pub fn present(&self, encoder: &mut CommandEncoder, surface_view: &TextureView) {
+        let Some(blit) = &self.blit else {
+            return;
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Blit Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: surface_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::default()),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&blit.pipeline);
+        render_pass.set_bind_group(0, &blit.bind_groups[self.frame], &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn entry_point(name: &str) -> String {
+    format!("fs_{}", name)
+}
+
+fn create_buffer_texture(device: &Device, label: &str, width: u32, height: u32) -> TextureView {
+    device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: BUFFER_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default())
+}
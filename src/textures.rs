@@ -0,0 +1,275 @@
+use std::{path::PathBuf, str::FromStr};
+
+use image::{imageops::FilterType, RgbaImage};
+use wgpu::{BindGroupEntry, BindGroupLayoutEntry, Device, Queue, Sampler, TextureView};
+
+/// The images share the group of the pointer, from this binding on
+const FIRST_BINDING: u32 = 2;
+/// A stage can sample 16 textures on every backend, the spectrogram and the passes take 6 of them
+pub const MAX_TEXTURES: usize = 10;
+
+/// An image file bound as a texture, and how it gets sampled
+#[derive(Clone)]
+pub struct TextureConfig {
+    pub path: PathBuf,
+    pub wrap: wgpu::AddressMode,
+    pub filter: wgpu::FilterMode,
+    // Generate smaller versions, for sampling the image shrunk down without aliasing
+    pub mipmaps: bool,
+    // Colors are stored sRGB encoded and read back linear, off for data like LUTs and masks
+    pub srgb: bool,
+}
+
+// eg. `lut.png,filter=nearest,wrap=clamp,linear`
+impl FromStr for TextureConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        let mut config = TextureConfig {
+            path: parts.next().unwrap_or_default().into(),
+            wrap: wgpu::AddressMode::ClampToEdge,
+            filter: wgpu::FilterMode::Linear,
+            mipmaps: false,
+            srgb: true,
+        };
+        for option in parts {
+            match option.split_once('=').unwrap_or((option, "")) {
+                ("wrap", "clamp") => config.wrap = wgpu::AddressMode::ClampToEdge,
+                ("wrap", "repeat") => config.wrap = wgpu::AddressMode::Repeat,
+                ("wrap", "mirror") => config.wrap = wgpu::AddressMode::MirrorRepeat,
+                ("filter", "linear") => config.filter = wgpu::FilterMode::Linear,
+                ("filter", "nearest") => config.filter = wgpu::FilterMode::Nearest,
+                ("mipmaps", "") => config.mipmaps = true,
+                ("linear", "") => config.srgb = false,
+                _ => {
+                    return Err(format!(
+                        "unknown texture option {}, expected wrap=clamp|repeat|mirror, \
+                         filter=linear|nearest, mipmaps or linear",
+                        option
+                    ))
+                }
+            }
+        }
+        if config.path.as_os_str().is_empty() {
+            return Err("the texture needs a path".to_string());
+        }
+        Ok(config)
+    }
+}
+
+/// A decoded image, and how it gets sampled
+pub struct Texture {
+    pub config: TextureConfig,
+    image: RgbaImage,
+}
+
+impl TextureConfig {
+    /// Decodes the image, so a broken file gets reported before anything is drawn
+    pub fn load(self) -> Result<Texture, String> {
+        let image = image::open(&self.path)
+            .map_err(|err| format!("{}: {}", self.path.display(), err))?
+            .into_rgba8();
+        Ok(Texture {
+            config: self,
+            image,
+        })
+    }
+}
+
+/// Uploads the images, with a sampler for each
+pub fn upload_textures(
+    device: &Device,
+    queue: &Queue,
+    textures: &[Texture],
+) -> Vec<(TextureView, Sampler)> {
+    textures
+        .iter()
+        .map(|texture| {
+            let config = &texture.config;
+            let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("image_sampler"),
+                address_mode_u: config.wrap,
+                address_mode_v: config.wrap,
+                address_mode_w: config.wrap,
+                mag_filter: config.filter,
+                min_filter: config.filter,
+                mipmap_filter: config.filter,
+                ..Default::default()
+            });
+            (create_texture(device, queue, texture), sampler)
+        })
+        .collect()
+}
+
+/// Entries of the uploaded images in the pointer group, texture `n` ends up at binding `2n + 2`
+/// and its sampler at `2n + 3`
+pub fn texture_entries(
+    textures: &[(TextureView, Sampler)],
+) -> (Vec<BindGroupLayoutEntry>, Vec<BindGroupEntry<'_>>) {
+    let mut layout_entries = vec![];
+    let mut entries = vec![];
+    for ((view, sampler), binding) in textures.iter().zip((FIRST_BINDING..).step_by(2)) {
+        layout_entries.push(wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        });
+        layout_entries.push(wgpu::BindGroupLayoutEntry {
+            binding: binding + 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        });
+        entries.push(wgpu::BindGroupEntry {
+            binding,
+            resource: wgpu::BindingResource::TextureView(view),
+        });
+        entries.push(wgpu::BindGroupEntry {
+            binding: binding + 1,
+            resource: wgpu::BindingResource::Sampler(sampler),
+        });
+    }
+    (layout_entries, entries)
+}
+
+fn create_texture(device: &Device, queue: &Queue, texture: &Texture) -> TextureView {
+    let config = &texture.config;
+    let (width, height) = texture.image.dimensions();
+    // Bigger images would fail validation, they get shrunk to the largest size the GPU takes
+    let max_size = device.limits().max_texture_dimension_2d;
+    let image = if width.max(height) > max_size {
+        let scale = max_size as f64 / width.max(height) as f64;
+        let size = |side: u32| ((side as f64 * scale) as u32).clamp(1, max_size);
+        eprintln!(
+            "{} is {}x{}, larger than the {} the GPU supports, it gets scaled down",
+            config.path.display(),
+            width,
+            height,
+            max_size
+        );
+        image::imageops::resize(
+            &texture.image,
+            size(width),
+            size(height),
+            FilterType::Triangle,
+        )
+    } else {
+        texture.image.clone()
+    };
+    let (width, height) = image.dimensions();
+    // Halved until 1x1
+    let mip_level_count = if config.mipmaps {
+        32 - width.max(height).leading_zeros()
+    } else {
+        1
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(&config.path.to_string_lossy()),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: if config.srgb {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        } else {
+            wgpu::TextureFormat::Rgba8Unorm
+        },
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+
+    let mut level = image;
+    for mip_level in 0..mip_level_count {
+        if mip_level > 0 {
+            let (width, height) = level.dimensions();
+            level = image::imageops::resize(
+                &level,
+                (width / 2).max(1),
+                (height / 2).max(1),
+                FilterType::Triangle,
+            );
+        }
+        let (width, height) = level.dimensions();
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &level,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * width),
+                rows_per_image: Some(height),
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults() {
+        let config: TextureConfig = "image.png".parse().unwrap();
+        assert_eq!(config.path, PathBuf::from("image.png"));
+        assert_eq!(config.wrap, wgpu::AddressMode::ClampToEdge);
+        assert_eq!(config.filter, wgpu::FilterMode::Linear);
+        assert!(!config.mipmaps);
+        assert!(config.srgb);
+    }
+
+    #[test]
+    fn options() {
+        let wrap = |s: &str| s.parse::<TextureConfig>().unwrap().wrap;
+        assert_eq!(wrap("a.png,wrap=repeat"), wgpu::AddressMode::Repeat);
+        assert_eq!(wrap("a.png,wrap=mirror"), wgpu::AddressMode::MirrorRepeat);
+        assert_eq!(
+            wrap("a.png,wrap=repeat,wrap=clamp"),
+            wgpu::AddressMode::ClampToEdge
+        );
+
+        let config: TextureConfig = "lut.png,filter=nearest,mipmaps,linear".parse().unwrap();
+        assert_eq!(config.path, PathBuf::from("lut.png"));
+        assert_eq!(config.filter, wgpu::FilterMode::Nearest);
+        assert!(config.mipmaps);
+        assert!(!config.srgb);
+        let config: TextureConfig = "lut.png,filter=nearest,filter=linear".parse().unwrap();
+        assert_eq!(config.filter, wgpu::FilterMode::Linear);
+    }
+
+    #[test]
+    fn invalid() {
+        for s in [
+            "a.png,wrap=border",
+            "a.png,filter",
+            "a.png,mipmaps=no",
+            "a.png,",
+        ] {
+            let err = s.parse::<TextureConfig>().err().unwrap();
+            assert!(err.starts_with("unknown texture option"), "{}", s);
+        }
+        for s in ["", ",linear"] {
+            let err = s.parse::<TextureConfig>().err().unwrap();
+            assert_eq!(err, "the texture needs a path");
+        }
+    }
+}
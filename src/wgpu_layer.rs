@@ -1,6 +1,6 @@
 use std::time::Instant;
 
-use crate::paper::Paper;
+use crate::{audio::stereo_buffer_len, gpu_fft::GpuFft, paper::Paper, passes::Passes};
 use smithay_client_toolkit::{
     compositor::CompositorHandler,
     delegate_compositor, delegate_layer, delegate_output, delegate_pointer, delegate_registry,
@@ -21,7 +21,10 @@ use wayland_client::{
     protocol::{wl_pointer, wl_seat, wl_surface},
     Connection, QueueHandle,
 };
-use wgpu::{util::DeviceExt, BindGroup, BindGroupLayout, Buffer, Device};
+use wgpu::{
+    util::DeviceExt, BindGroup, BindGroupEntry, BindGroupLayout, BindGroupLayoutEntry, Buffer,
+    Device,
+};
 
 pub struct WgpuLayer {
     pub start_time: Instant,
@@ -33,10 +36,62 @@ pub struct WgpuLayer {
     pub surface: wgpu::Surface,
     pub render_pipeline: wgpu::RenderPipeline,
 
+    pub elapsed_time_group_layout: wgpu::BindGroupLayout,
     pub elapsed_time_bind_group: wgpu::BindGroup,
     pub elapsed_time_buffer: wgpu::Buffer,
+    pub pointer_group_layout: wgpu::BindGroupLayout,
     pub pointer_bind_group: wgpu::BindGroup,
     pub pointer_buffer: wgpu::Buffer,
+    pub audio_group_layout: wgpu::BindGroupLayout,
+    pub audio_bind_group: wgpu::BindGroup,
+    pub audio_buffers: AudioBuffers,
+    pub gpu_fft: Option<GpuFft>,
+    pub passes: Passes,
+}
+
+impl WgpuLayer {
+    /// Layouts of every group but the one of the buffer passes
+    pub fn shared_layouts(&self) -> [&wgpu::BindGroupLayout; 3] {
+        [
+            &self.elapsed_time_group_layout,
+            &self.pointer_group_layout,
+            &self.audio_group_layout,
+        ]
+    }
+}
+
+// GPU side of the audio data, all of them live in the audio bind group (group: 2)
+pub struct AudioBuffers {
+    // [channels, samples per channel, spectrum bins per channel, sample rate], binding: 0
+    pub info_buffer: wgpu::Buffer,
+    // Waveform of every channel, one after the other, binding: 1
+    pub waveform_buffer: wgpu::Buffer,
+    // FFT magnitudes of every channel, one after the other, binding: 2
+    pub spectrum_buffer: wgpu::Buffer,
+    // Frequency bands, one vec4 per band, binding: 3
+    pub bands_buffer: wgpu::Buffer,
+    // [seconds since the last beat, beat count, onset strength, padding],
+    // [bpm, beat phase, tempo confidence, padding], binding: 4
+    pub beat_buffer: wgpu::Buffer,
+    // [1 if the source delivers samples else 0, seconds since that last changed, padding, padding], binding: 5
+    pub status_buffer: wgpu::Buffer,
+    // Levels, all the channels together then one vec4 per channel, binding: 6
+    pub levels_buffer: wgpu::Buffer,
+    // Past spectra, one row per update and one layer per channel, binding: 7
+    pub spectrogram_texture: wgpu::Texture,
+    // [row of the newest spectrum, rows, padding, padding], binding: 8
+    pub spectrogram_info_buffer: wgpu::Buffer,
+    pub spectrogram_row: u32,
+    // Chroma then the dominant pitch, 4 vec4, binding: 9
+    pub pitch_buffer: wgpu::Buffer,
+    // Spectral features, one vec4 per channel, binding: 10
+    pub features_buffer: wgpu::Buffer,
+    // Mid/side of every sample then the vectorscope points, as vec2, binding: 11
+    pub stereo_buffer: wgpu::Buffer,
+    // [phase correlation, width, vectorscope points, padding], binding: 12
+    pub stereo_info_buffer: wgpu::Buffer,
+    // Triggered oscilloscope window of every channel, one after the other, binding: 13
+    pub scope_buffer: wgpu::Buffer,
 }
 
 // Boilerplate Papaer implements
@@ -88,7 +143,7 @@ impl SeatHandler for Paper {
         capability: Capability,
     ) {
         if capability == Capability::Pointer && self.pointer.is_none() {
-            println!("Set pointer capability");
+            eprintln!("Set pointer capability");
             let pointer = self
                 .seat_state
                 .get_pointer(qh, &seat)
@@ -105,7 +160,7 @@ impl SeatHandler for Paper {
         capability: Capability,
     ) {
         if capability == Capability::Pointer && self.pointer.is_some() {
-            println!("Set pointer capability");
+            eprintln!("Set pointer capability");
             self.pointer = None;
         }
     }
@@ -170,7 +225,7 @@ impl LayerShellHandler for Paper {
             self.width = Some(configure.new_size.0);
             self.height = Some(configure.new_size.1);
         }
-        if let Some(wgpu_layer) = &self.wgpu_layer {
+        if let Some(wgpu_layer) = &mut self.wgpu_layer {
             let cap = wgpu_layer.surface.get_capabilities(&wgpu_layer.adapter);
             let surface_config = wgpu::SurfaceConfiguration {
                 usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -187,18 +242,25 @@ impl LayerShellHandler for Paper {
             wgpu_layer
                 .surface
                 .configure(&wgpu_layer.device, &surface_config);
+            wgpu_layer.passes.resize(
+                &wgpu_layer.device,
+                self.width.unwrap(),
+                self.height.unwrap(),
+            );
 
             self.draw(qh);
         }
     }
 }
 
+/// `extra_entries` go in the same group, after the buffer
 pub fn create_gpu_buffer(
     device: &Device,
     label: &str,
     binding: u32,
     contents: &[u8],
     has_dynamic_offset: bool,
+    (extra_layout_entries, extra_entries): (Vec<BindGroupLayoutEntry>, Vec<BindGroupEntry>),
 ) -> (Buffer, BindGroupLayout, BindGroup) {
     let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: Some(&format!("{}_buffer", label)),
@@ -217,7 +279,10 @@ pub fn create_gpu_buffer(
                 has_dynamic_offset,
                 min_binding_size: None,
             },
-        }],
+        }]
+        .into_iter()
+        .chain(extra_layout_entries)
+        .collect::<Vec<_>>(),
         label: Some(&format!("{}_group_layout", label)),
     });
 
@@ -227,8 +292,347 @@ pub fn create_gpu_buffer(
         entries: &[wgpu::BindGroupEntry {
             binding,
             resource: buffer.as_entire_binding(),
-        }],
+        }]
+        .into_iter()
+        .chain(extra_entries)
+        .collect::<Vec<_>>(),
         label: Some(&format!("{}_bind_group", label)),
     });
     (buffer, layout, group)
 }
+
+/// Pipeline drawing one triangle over the whole target, with `vs_main` and the given fragment entry point
+pub fn create_render_pipeline(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+) -> wgpu::RenderPipeline {
+    let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Render Pipeline Layout"),
+        bind_group_layouts,
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(&render_pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point,
+            // Add the target
+            targets: &[Some(wgpu::ColorTargetState {
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+                format,
+            })],
+        }),
+        primitive: Default::default(),
+        depth_stencil: Default::default(),
+        multisample: Default::default(),
+        multiview: Default::default(),
+    })
+}
+
+/// Parses the wgsl source, the error comes formatted for the terminal
+pub fn parse_shader(source: &str) -> Result<naga::Module, String> {
+    naga::front::wgsl::parse_str(source).map_err(|err| err.emit_to_string(source))
+}
+
+/// Whether any function of the shader reads a resource whose binding matches
+pub fn uses_binding(
+    module: &naga::Module,
+    matches: impl Fn(&naga::ResourceBinding) -> bool,
+) -> bool {
+    let functions = module
+        .functions
+        .iter()
+        .map(|(_, function)| function)
+        .chain(module.entry_points.iter().map(|entry| &entry.function));
+    functions
+        .flat_map(|function| function.expressions.iter())
+        .any(|(_, expression)| match expression {
+            naga::Expression::GlobalVariable(variable) => module.global_variables[*variable]
+                .binding
+                .as_ref()
+                .is_some_and(&matches),
+            _ => false,
+        })
+}
+
+fn create_storage_buffer(device: &Device, label: &str, size: usize) -> Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(&format!("{}_buffer", label)),
+        // Empty bindings are not allowed, so always allocate at least one element
+        size: (size.max(1) * std::mem::size_of::<f32>()) as u64,
+        usage: wgpu::BufferUsages::STORAGE
+            | wgpu::BufferUsages::COPY_DST
+            | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    })
+}
+
+pub fn create_audio_buffers(
+    device: &Device,
+    channels: usize,
+    samples: usize,
+    bins: usize,
+    bands: usize,
+    spectrogram_rows: u32,
+    scope_samples: usize,
+) -> (AudioBuffers, BindGroupLayout, BindGroup) {
+    let info_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("audio_info_buffer"),
+        contents: bytemuck::cast_slice(&[0u32; 4]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    let waveform_buffer = create_storage_buffer(device, "audio_waveform", channels * samples);
+    let spectrum_buffer = create_storage_buffer(device, "audio_spectrum", channels * bins);
+    let bands_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("audio_bands_buffer"),
+        contents: bytemuck::cast_slice(&vec![[0.0f32; 4]; bands.max(1)]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    let beat_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("audio_beat_buffer"),
+        contents: bytemuck::cast_slice(&[[0.0f32; 4]; 2]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    let status_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("audio_status_buffer"),
+        contents: bytemuck::cast_slice(&[0.0f32; 4]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    let levels_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("audio_levels_buffer"),
+        contents: bytemuck::cast_slice(&vec![[0.0f32; 4]; channels + 1]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    // Filled straight from the spectrum buffer, so it works with the gpu fft too
+    let spectrogram_texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("audio_spectrogram_texture"),
+        size: wgpu::Extent3d {
+            width: bins.max(1) as u32,
+            height: spectrogram_rows.max(1),
+            depth_or_array_layers: channels.max(1) as u32,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::R32Float,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    let spectrogram_view = spectrogram_texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        ..Default::default()
+    });
+    let spectrogram_info_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("audio_spectrogram_info_buffer"),
+        contents: bytemuck::cast_slice(&[0u32, spectrogram_rows.max(1), 0, 0]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    let pitch_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("audio_pitch_buffer"),
+        contents: bytemuck::cast_slice(&[[0.0f32; 4]; 4]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    let features_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("audio_features_buffer"),
+        contents: bytemuck::cast_slice(&vec![[0.0f32; 4]; channels.max(1)]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    let stereo_buffer =
+        create_storage_buffer(device, "audio_stereo", 2 * stereo_buffer_len(samples));
+    let stereo_info_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("audio_stereo_info_buffer"),
+        contents: bytemuck::cast_slice(&[0.0f32; 4]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    let scope_buffer = create_storage_buffer(device, "audio_scope", channels * scope_samples);
+
+    let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        count: None,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+    };
+    let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        count: None,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+    };
+
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            uniform_entry(0),
+            storage_entry(1),
+            storage_entry(2),
+            uniform_entry(3),
+            uniform_entry(4),
+            uniform_entry(5),
+            uniform_entry(6),
+            wgpu::BindGroupLayoutEntry {
+                binding: 7,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                count: None,
+                // R32Float can't be filtered everywhere, shaders read it with textureLoad
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+            },
+            uniform_entry(8),
+            uniform_entry(9),
+            uniform_entry(10),
+            storage_entry(11),
+            uniform_entry(12),
+            storage_entry(13),
+        ],
+        label: Some("audio_group_layout"),
+    });
+
+    let group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: info_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: waveform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: spectrum_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: bands_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: beat_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: status_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: levels_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: wgpu::BindingResource::TextureView(&spectrogram_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: spectrogram_info_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 9,
+                resource: pitch_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 10,
+                resource: features_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 11,
+                resource: stereo_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 12,
+                resource: stereo_info_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 13,
+                resource: scope_buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("audio_bind_group"),
+    });
+
+    (
+        AudioBuffers {
+            info_buffer,
+            waveform_buffer,
+            spectrum_buffer,
+            bands_buffer,
+            beat_buffer,
+            status_buffer,
+            levels_buffer,
+            spectrogram_texture,
+            spectrogram_info_buffer,
+            spectrogram_row: 0,
+            pitch_buffer,
+            features_buffer,
+            stereo_buffer,
+            stereo_info_buffer,
+            scope_buffer,
+        },
+        layout,
+        group,
+    )
+}
+
+/// Copies the current spectrum of every channel into the next row of the spectrogram
+pub fn push_spectrogram_row(
+    queue: &wgpu::Queue,
+    encoder: &mut wgpu::CommandEncoder,
+    audio_buffers: &mut AudioBuffers,
+) {
+    let size = audio_buffers.spectrogram_texture.size();
+    let (bins, rows, channels) = (size.width, size.height, size.depth_or_array_layers);
+    audio_buffers.spectrogram_row = (audio_buffers.spectrogram_row + 1) % rows;
+    for channel in 0..channels {
+        encoder.copy_buffer_to_texture(
+            wgpu::ImageCopyBuffer {
+                buffer: &audio_buffers.spectrum_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: (channel * bins) as u64 * std::mem::size_of::<f32>() as u64,
+                    bytes_per_row: None,
+                    rows_per_image: None,
+                },
+            },
+            wgpu::ImageCopyTexture {
+                texture: &audio_buffers.spectrogram_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: audio_buffers.spectrogram_row,
+                    z: channel,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::Extent3d {
+                width: bins,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+    queue.write_buffer(
+        &audio_buffers.spectrogram_info_buffer,
+        0,
+        bytemuck::cast_slice(&[audio_buffers.spectrogram_row, rows, 0, 0]),
+    );
+}